@@ -0,0 +1,78 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! Prints the responding addresses of an i2c bus in a grid like i2cdetect.
+//!
+//! Usage: `cargo run --example i2cdetect [BUS]`. The default bus is 1.
+//!
+
+extern crate rustpi_io;
+
+use rustpi_io::i2c;
+use rustpi_io::i2c::I2cDevice;
+use std::env;
+use std::process;
+
+fn main() {
+    let bus = match env::args().nth(1) {
+        None => 1,
+        Some(arg) => match arg.parse::<u8>() {
+            Ok(bus) => bus,
+            Err(_) => {
+                eprintln!("Error: \"{}\" is no i2c bus number", arg);
+                process::exit(2);
+            }
+        },
+    };
+    let mut device = match I2cDevice::open(bus) {
+        Ok(device) => device,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    let found = match i2c::scan(&mut device) {
+        Ok(found) => found,
+        Err(error) => {
+            eprintln!("Error: can't scan i2c bus {}: {}", bus, error);
+            process::exit(1);
+        }
+    };
+
+    // the row labels are 3 characters wide, like in i2c-tools
+    print!("   ");
+    for column in 0..16 {
+        print!("  {:x}", column);
+    }
+    println!();
+    for row in (0..0x80_u8).step_by(16) {
+        print!("{:02x}:", row);
+        for address in row..row + 16 {
+            if !(i2c::SCAN_FIRST..=i2c::SCAN_LAST).contains(&address) {
+                print!("   ");
+            } else if found.contains(&address) {
+                print!(" {:02x}", address);
+            } else {
+                print!(" --");
+            }
+        }
+        println!();
+    }
+}
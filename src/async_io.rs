@@ -0,0 +1,187 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! Async wrappers for tokio. Only available with the `async` feature.
+//!
+//! Spi transfers are offloaded to the blocking thread pool of tokio. Gpio interrupts are
+//! registered at the reactor of tokio, so awaiting an edge does not block any thread.
+//!
+
+use crate::error::{Error, Result};
+use crate::globals::GPIO_PATH;
+use crate::gpio::{Edge, GPIOData, GPIOMode, GPIO};
+use crate::serial::SerialPi;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::{Arc, Mutex};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::task;
+
+/**
+ * A [`SerialPi`] that can be used from async code.
+ *
+ * Every function moves the transfer to the blocking thread pool of tokio. The wrapper can be cloned to share the device between tasks, the transfers are serialized.
+ *
+ * [`SerialPi`]: ../serial/struct.SerialPi.html
+ */
+#[derive(Clone)]
+pub struct AsyncSerialPi {
+    serial: Arc<Mutex<SerialPi>>,
+}
+
+impl AsyncSerialPi {
+    pub fn new(serial: SerialPi) -> AsyncSerialPi {
+        AsyncSerialPi {
+            serial: Arc::new(Mutex::new(serial)),
+        }
+    }
+
+    /**
+     * Calls `f` with the wrapped [`SerialPi`] on the blocking thread pool.
+     *
+     * [`SerialPi`]: ../serial/struct.SerialPi.html
+     */
+    pub async fn with<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut SerialPi) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let serial = self.serial.clone();
+        task::spawn_blocking(move || {
+            let mut serial = serial.lock().map_err(|_| Error::LockPoisoned)?;
+            f(&mut serial)
+        })
+        .await
+        .map_err(|_| Error::ThreadFailed("blocking spi task failed"))?
+    }
+
+    /**
+     * Does a full duplex transfer of `tx` and returns the received bytes. See `SerialPi::transfer`.
+     */
+    pub async fn transfer(&self, tx: Vec<u8>) -> Result<Vec<u8>> {
+        self.with(move |serial| {
+            let mut rx = vec![0_u8; tx.len()];
+            serial.transfer(&tx, &mut rx)?;
+            Ok(rx)
+        })
+        .await
+    }
+
+    /**
+     * Writes all bytes with the `Write` implementation of [`SerialPi`], so the received bytes are buffered in full duplex mode.
+     *
+     * [`SerialPi`]: ../serial/struct.SerialPi.html
+     */
+    pub async fn write(&self, tx: Vec<u8>) -> Result<()> {
+        self.with(move |serial| Ok(serial.write_all(&tx)?)).await
+    }
+
+    /**
+     * Reads `len` bytes with the `Read` implementation of [`SerialPi`], so buffered bytes are returned first.
+     *
+     * [`SerialPi`]: ../serial/struct.SerialPi.html
+     */
+    pub async fn read(&self, len: usize) -> Result<Vec<u8>> {
+        self.with(move |serial| {
+            let mut rx = vec![0_u8; len];
+            serial.read_exact(&mut rx)?;
+            Ok(rx)
+        })
+        .await
+    }
+}
+
+/**
+ * A gpio in read mode whose interrupts can be awaited.
+ *
+ * The value file of the gpio is registered at the tokio reactor. The kernel signals an interrupt on the configured [`Edge`] as priority event on this file.
+ * Has to be created inside a tokio runtime.
+ *
+ * [`Edge`]: ../gpio/enum.Edge.html
+ */
+pub struct AsyncGPIO {
+    gpio: GPIO,
+    value: AsyncFd<File>,
+}
+
+impl AsyncGPIO {
+    /**
+     * Switches the gpio into read mode, selects the edges that raise an interrupt and registers the value file at the reactor.
+     *
+     * # Errors
+     * Returns an error if the gpio can't be configured.
+     *
+     * # Panics
+     * Panics if it is not called inside a tokio runtime.
+     */
+    pub fn new(mut gpio: GPIO, edge: Edge) -> Result<AsyncGPIO> {
+        if gpio.current_mode() != GPIOMode::Read {
+            gpio.set_mode(GPIOMode::Read)?;
+        }
+        gpio.set_edge(edge)?;
+        let value = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(format!("{}gpio{}/value", GPIO_PATH, gpio.gpio_number()))?;
+        // SAFETY: the AsyncFd owns the file, so the descriptor stays open and unchanged as long as it is registered
+        let value = unsafe { AsyncFd::register_with_interest(value, Interest::PRIORITY) }?;
+        let async_gpio = AsyncGPIO { gpio, value };
+        // reading the file resets a pending interrupt
+        async_gpio.value()?;
+        Ok(async_gpio)
+    }
+
+    /**
+     * Waits for the next interrupt and returns the value that was read afterwards.
+     */
+    pub async fn wait_for_edge(&mut self) -> Result<GPIOData> {
+        let mut guard = self.value.ready(Interest::PRIORITY).await?;
+        guard.clear_ready();
+        drop(guard);
+        self.value()
+    }
+
+    /**
+     * Reads the current value of the pin without waiting.
+     */
+    pub fn value(&self) -> Result<GPIOData> {
+        let mut file = self.value.get_ref();
+        file.seek(SeekFrom::Start(0))?;
+        let mut buffer = [0_u8; 1];
+        file.read_exact(&mut buffer)?;
+        match buffer[0] {
+            b'0' => Ok(GPIOData::Low),
+            b'1' => Ok(GPIOData::High),
+            _ => Err(Error::parse(
+                "gpio value",
+                &String::from_utf8_lossy(&buffer),
+            )),
+        }
+    }
+
+    /**
+     * Returns the wrapped gpio. The configured edges stay active.
+     */
+    pub fn into_inner(self) -> GPIO {
+        self.gpio
+    }
+}
@@ -0,0 +1,185 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! The error type of all modules.
+//!
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::gpio::GPIOMode;
+use crate::modbus::Exception;
+
+/// The errors of the crate.
+///
+/// Every error can be converted into an `io::Error` with a fitting `ErrorKind`, so it can be
+/// returned from `Read` and `Write` implementations and by code that works with `io::Result`.
+/// Converting such an `io::Error` back returns the original error.
+#[derive(Debug)]
+pub enum Error {
+    /// The gpio was already exported earlier (inside or outside of the application)
+    PinInUse { pin: u8 },
+    /// The operation needs the gpio in another mode
+    WrongMode { pin: u8, required: GPIOMode },
+    /// A device file or a directory of the system interface can't be opened
+    DeviceNotFound { path: PathBuf, source: io::Error },
+    /// The spi device can't be opened, spi is probably not enabled in /boot/config.txt
+    SpiNotEnabled { source: io::Error },
+    /// The revision code of the raspberry pi is not supported
+    UnsupportedRevision { revision: u32 },
+    /// A value read from the system or a device has an unexpected format
+    Parse { what: &'static str, input: String },
+    /// A field is missing in information read from the system, e.g. the revision in /proc/cpuinfo
+    MissingField { field: &'static str },
+    /// An argument is out of range or does not fit to the configuration
+    InvalidArgument(&'static str),
+    /// A spi transfer is larger than the driver accepts at once
+    TransferTooLarge { len: usize, max: usize },
+    /// The device or its driver does not support the operation
+    Unsupported(&'static str),
+    /// A device did not answer in time
+    Timeout(&'static str),
+    /// A device did not follow the protocol, e.g. it did not acknowledge or sent a wrong checksum
+    Protocol(&'static str),
+    /// A modbus slave answered with an exception
+    ModbusException(Exception),
+    /// The internal read buffer is full and the overflow policy does not allow to grow it
+    BufferFull,
+    /// A thread panicked while it was using a shared device
+    LockPoisoned,
+    /// A worker thread did not start or panicked
+    ThreadFailed(&'static str),
+    /// Any other error of the operating system
+    Io(io::Error),
+}
+
+/// A `Result` with the error type of the crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Returns the `ErrorKind` of the corresponding `io::Error`.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::PinInUse { .. } => ErrorKind::AddrInUse,
+            Error::WrongMode { .. } => ErrorKind::PermissionDenied,
+            Error::DeviceNotFound { .. } | Error::SpiNotEnabled { .. } => ErrorKind::NotFound,
+            Error::UnsupportedRevision { .. }
+            | Error::Parse { .. }
+            | Error::MissingField { .. }
+            | Error::Protocol(_) => ErrorKind::InvalidData,
+            Error::InvalidArgument(_) | Error::TransferTooLarge { .. } => ErrorKind::InvalidInput,
+            Error::Unsupported(_) => ErrorKind::Unsupported,
+            Error::Timeout(_) => ErrorKind::TimedOut,
+            Error::ModbusException(_)
+            | Error::BufferFull
+            | Error::LockPoisoned
+            | Error::ThreadFailed(_) => ErrorKind::Other,
+            Error::Io(error) => error.kind(),
+        }
+    }
+
+    /// Wraps the error of the last call to the operating system
+    pub(crate) fn last_os_error() -> Error {
+        Error::Io(io::Error::last_os_error())
+    }
+
+    pub(crate) fn parse(what: &'static str, input: &str) -> Error {
+        Error::Parse {
+            what,
+            input: input.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::PinInUse { pin } => write!(f, "gpio {} was already initialized", pin),
+            Error::WrongMode { pin, required } => {
+                write!(f, "gpio {} is not in {:?} mode", pin, required)
+            }
+            Error::DeviceNotFound { path, source } => {
+                write!(f, "unable to open {}: {}", path.display(), source)
+            }
+            Error::SpiNotEnabled { .. } => write!(
+                f,
+                "unable to open the spi device. Did you set \"dtparam=spi=on\" in /boot/config.txt?"
+            ),
+            Error::UnsupportedRevision { revision } => {
+                write!(f, "revision {:x} is not supported", revision)
+            }
+            Error::Parse { what, input } => write!(f, "invalid {}: \"{}\"", what, input),
+            Error::MissingField { field } => write!(f, "missing field {}", field),
+            Error::InvalidArgument(message)
+            | Error::Unsupported(message)
+            | Error::Timeout(message)
+            | Error::Protocol(message)
+            | Error::ThreadFailed(message) => write!(f, "{}", message),
+            Error::TransferTooLarge { len, max } => write!(
+                f,
+                "transfer of {} bytes exceeds the spidev limit of {} bytes",
+                len, max
+            ),
+            Error::ModbusException(exception) => write!(f, "{}", exception),
+            Error::BufferFull => write!(f, "internal read buffer is full"),
+            Error::LockPoisoned => write!(f, "lock of a shared device is poisoned"),
+            Error::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::DeviceNotFound { source, .. } | Error::SpiNotEnabled { source } => Some(source),
+            Error::ModbusException(exception) => Some(exception),
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        match error.downcast::<Error>() {
+            Ok(error) => error,
+            Err(error) => Error::Io(error),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> From<tokio::io::unix::AsyncFdRegisterError<T>> for Error {
+    fn from(error: tokio::io::unix::AsyncFdRegisterError<T>) -> Error {
+        Error::Io(error.into())
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        match error {
+            Error::Io(error) => error,
+            error => io::Error::new(error.kind(), error),
+        }
+    }
+}
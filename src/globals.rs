@@ -2,7 +2,23 @@
 pub const GPIO_PATH: &str = "/sys/class/gpio/";
 /// Path to the hardware information
 pub const RASPI_INFO_PATH: &str = "/proc/cpuinfo";
+/// Path to the proc filesystem with cpuinfo and the device tree
+pub const PROC_PATH: &str = "/proc/";
 /// Path to the first spi interface
 pub const SPI_PATH0: &str = "/dev/spidev0.0";
 /// Path to the second spi interface
 pub const SPI_PATH1: &str = "/dev/spidev0.1";
+/// Path to the transfer size limit of the spidev driver
+pub const SPIDEV_BUFSIZ_PATH: &str = "/sys/module/spidev/parameters/bufsiz";
+/// Path to the pwm interface
+pub const PWM_PATH: &str = "/sys/class/pwm/";
+/// Path prefix of the i2c buses
+pub const I2C_PATH: &str = "/dev/i2c-";
+/// Path to the alias of the uart on the gpio header
+pub const SERIAL0_PATH: &str = "/dev/serial0";
+/// Path to the PL011 uart
+pub const TTYAMA0_PATH: &str = "/dev/ttyAMA0";
+/// Path to the mini uart
+pub const TTYS0_PATH: &str = "/dev/ttyS0";
+/// Path to the 1-wire devices
+pub const W1_PATH: &str = "/sys/bus/w1/devices/";
@@ -20,12 +20,10 @@
 use std::fmt;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
-use std::io::Error;
-use std::io::ErrorKind;
-use std::io::Result;
 use std::path::Path;
 
-use globals::GPIO_PATH;
+use crate::error::{Error, Result};
+use crate::globals::GPIO_PATH;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GPIOMode {
@@ -33,12 +31,21 @@ pub enum GPIOMode {
     Write,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GPIOData {
     Low = 0,
     High = 1,
 }
 
+/// The signal edges that raise an interrupt on a gpio in read mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    None,
+    Rising,
+    Falling,
+    Both,
+}
+
 /// A software representation for a GPIO pin of the raspberry pi.
 pub struct GPIO {
     pin: u8,
@@ -71,18 +78,20 @@ impl GPIO {
 
     /// Initializes the gpio. Exports the pin with the /sys/class/gpio/export file
     /// and calls the set_mode() function with the given mode.
-    /// Returns an Error if the gpio was already exported earlier (inside or outside of the application)
+    /// Returns Error::PinInUse if the gpio was already exported earlier (inside or outside of the application)
     pub fn new(gpio: u8, mode: GPIOMode) -> Result<Self> {
         if Path::new(&format!("{}gpio{}/", GPIO_PATH, gpio)).exists() {
-            return Err(Error::new(
-                ErrorKind::AddrInUse,
-                "Error: gpio was already initialized",
-            ));
+            return Err(Error::PinInUse { pin: gpio });
         }
         {
+            let path = format!("{}export", GPIO_PATH);
             let mut export = OpenOptions::new()
                 .write(true)
-                .open(format!("{}export", GPIO_PATH))?;
+                .open(&path)
+                .map_err(|source| Error::DeviceNotFound {
+                    path: path.into(),
+                    source,
+                })?;
             export.write_all(format!("{}", gpio).as_bytes())?;
         }
         let mut result = GPIO { pin: gpio, mode };
@@ -91,31 +100,31 @@ impl GPIO {
     }
 
     /// Reads the current value of the pin in both Read and Write mode.
-    /// Returns an Error if a value other than "1" or "0" is read
+    /// Returns Error::Parse if a value other than "1" or "0" is read
     pub fn value(&self) -> Result<GPIOData> {
         let mut value = OpenOptions::new()
             .read(true)
             .open(format!("{}gpio{}/value", GPIO_PATH, self.pin))?;
         let mut buffer = vec![];
         value.read_to_end(&mut buffer)?;
-        match buffer[0] as char {
-            '0' => Ok(GPIOData::Low),
-            '1' => Ok(GPIOData::High),
-            _ => Err(Error::new(
-                ErrorKind::InvalidData,
-                "read value other than 1 or 0",
+        match buffer.first() {
+            Some(b'0') => Ok(GPIOData::Low),
+            Some(b'1') => Ok(GPIOData::High),
+            _ => Err(Error::parse(
+                "gpio value",
+                String::from_utf8_lossy(&buffer).trim(),
             )),
         }
     }
 
     /// Sets the value of the gpio to HIGH or LOW
-    /// Returns an Error if the GPIO::Mode is not Write
+    /// Returns Error::WrongMode if the GPIO::Mode is not Write
     pub fn set(&self, data: GPIOData) -> Result<()> {
         if self.mode != GPIOMode::Write {
-            return Err(Error::new(
-                ErrorKind::PermissionDenied,
-                "Error: gpio is not in write mode",
-            ));
+            return Err(Error::WrongMode {
+                pin: self.pin,
+                required: GPIOMode::Write,
+            });
         }
         let buffer = match data {
             GPIOData::Low => "0",
@@ -127,6 +136,81 @@ impl GPIO {
         direction.write_all(buffer.as_bytes())?;
         Ok(())
     }
+
+    /// Selects the signal edges that raise an interrupt by writing the edge file of the gpio.
+    /// Interrupts can be awaited with [`AsyncGPIO`] (requires the `async` feature).
+    /// Returns Error::WrongMode if the GPIO::Mode is not Read
+    ///
+    /// [`AsyncGPIO`]: ../async_io/struct.AsyncGPIO.html
+    pub fn set_edge(&self, edge: Edge) -> Result<()> {
+        if self.mode != GPIOMode::Read {
+            return Err(Error::WrongMode {
+                pin: self.pin,
+                required: GPIOMode::Read,
+            });
+        }
+        let mut edge_file = OpenOptions::new()
+            .write(true)
+            .open(format!("{}gpio{}/edge", GPIO_PATH, self.pin))?;
+        edge_file.write_all(edge.to_string().as_bytes())?;
+        Ok(())
+    }
+}
+
+/// A digital pin that can be driven and read.
+/// It is implemented by [`GPIO`] and allows drivers like the bit banged spi
+/// to run on simulated pins as well.
+///
+/// [`GPIO`]: ./struct.GPIO.html
+pub trait DigitalPin {
+    /// Drives the pin to HIGH or LOW
+    fn set(&self, data: GPIOData) -> Result<()>;
+    /// Reads the current level of the pin
+    fn value(&self) -> Result<GPIOData>;
+}
+
+impl DigitalPin for GPIO {
+    fn set(&self, data: GPIOData) -> Result<()> {
+        GPIO::set(self, data)
+    }
+
+    fn value(&self) -> Result<GPIOData> {
+        GPIO::value(self)
+    }
+}
+
+/// A pin of an open drain bus like i2c. The line is pulled up externally, so the pin
+/// either drives it LOW or releases it and lets it float HIGH.
+/// It is implemented by [`GPIO`] by switching between write mode and read mode.
+///
+/// [`GPIO`]: ./struct.GPIO.html
+pub trait OpenDrainPin {
+    /// Drives the line LOW
+    fn drive_low(&mut self) -> Result<()>;
+    /// Stops driving the line, so that it is pulled HIGH unless another device drives it
+    fn release(&mut self) -> Result<()>;
+    /// Reads the current level of the line
+    fn level(&self) -> Result<GPIOData>;
+}
+
+impl OpenDrainPin for GPIO {
+    fn drive_low(&mut self) -> Result<()> {
+        if self.mode != GPIOMode::Write {
+            self.set_mode(GPIOMode::Write)?;
+        }
+        self.set(GPIOData::Low)
+    }
+
+    fn release(&mut self) -> Result<()> {
+        if self.mode != GPIOMode::Read {
+            self.set_mode(GPIOMode::Read)?;
+        }
+        Ok(())
+    }
+
+    fn level(&self) -> Result<GPIOData> {
+        self.value()
+    }
 }
 
 /// Closes the gpio and write its pin number into /sys/class/gpio/unexport
@@ -160,3 +244,15 @@ impl fmt::Display for GPIOMode {
         }
     }
 }
+
+///Writes "none", "rising", "falling" or "both" like the edge file of the gpio
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Edge::None => write!(f, "none"),
+            Edge::Rising => write!(f, "rising"),
+            Edge::Falling => write!(f, "falling"),
+            Edge::Both => write!(f, "both"),
+        }
+    }
+}
@@ -0,0 +1,422 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! I2C master on the /dev/i2c-N interface of the linux kernel.
+//!
+
+use crate::error::{Error, Result};
+use crate::globals::I2C_PATH;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+
+/// Request number of the combined read/write ioctl
+const I2C_RDWR: libc::Ioctl = 0x0707;
+/// Message flag for a read
+const I2C_M_RD: u16 = 0x0001;
+/// Message flag for a ten bit address
+const I2C_M_TEN: u16 = 0x0010;
+/// Message flag for a read whose length is given by the first received byte
+const I2C_M_RECV_LEN: u16 = 0x0400;
+/// Maximal data length of a SMBus block transfer
+pub(crate) const SMBUS_BLOCK_MAX: usize = 32;
+
+/// struct i2c_msg of the kernel
+#[repr(C)]
+struct I2cMsg {
+    addr: u16,
+    flags: u16,
+    len: u16,
+    buf: *mut u8,
+}
+
+/// struct i2c_rdwr_ioctl_data of the kernel
+#[repr(C)]
+struct I2cRdwrIoctlData {
+    msgs: *mut I2cMsg,
+    nmsgs: u32,
+}
+
+/**
+ * The address of an i2c slave.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Address {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl Address {
+    fn check(self) -> Result<Address> {
+        let valid = match self {
+            Address::SevenBit(address) => address <= 0x7F,
+            Address::TenBit(address) => address <= 0x3FF,
+        };
+        if !valid {
+            return Err(Error::InvalidArgument("i2c address is out of range"));
+        }
+        Ok(self)
+    }
+}
+
+/**
+ * A single message of a combined i2c transaction.
+ */
+pub enum Message<'a> {
+    /// Sends all bytes to the slave
+    Write(&'a [u8]),
+    /// Fills the buffer with bytes from the slave
+    Read(&'a mut [u8]),
+    /// Reads a count byte into the first element of the buffer and then as many bytes as the count (SMBus block read).
+    /// The buffer needs space for the count byte and 32 data bytes.
+    ReadBlock(&'a mut [u8]),
+}
+
+/**
+ * A bus that can execute i2c transactions.
+ *
+ * It is implemented by [`I2cDevice`] for the kernel interface. [`I2cPi`] builds all of its functions on this trait, so another implementation can be used to mock the bus.
+ *
+ * [`I2cDevice`]: ./struct.I2cDevice.html
+ * [`I2cPi`]: ./struct.I2cPi.html
+ */
+pub trait I2cTransport {
+    /// Executes the messages as one transaction: A start condition, the messages separated
+    /// by repeated starts and a stop condition. Every message is addressed to `address`.
+    fn transfer(&mut self, address: Address, messages: &mut [Message]) -> Result<()>;
+}
+
+/**
+ * An i2c bus of the kernel, e.g. /dev/i2c-1.
+ */
+pub struct I2cDevice {
+    file: File,
+}
+
+impl I2cDevice {
+    /**
+     * Opens /dev/i2c-`bus`.
+     *
+     * # Errors
+     * Returns `Error::DeviceNotFound` if the i2c device can't be opened. The raspberry pi might not be configured correctly. Check the [documentation](https://www.raspberrypi.org/documentation/configuration/config-txt/README.md) of the raspberry pi in this case.
+     */
+    pub fn open(bus: u8) -> Result<I2cDevice> {
+        let path = format!("{}{}", I2C_PATH, bus);
+        match OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(file) => Ok(I2cDevice { file }),
+            Err(source) => Err(Error::DeviceNotFound {
+                path: path.into(),
+                source,
+            }),
+        }
+    }
+}
+
+impl I2cTransport for I2cDevice {
+    fn transfer(&mut self, address: Address, messages: &mut [Message]) -> Result<()> {
+        let (addr, address_flags) = match address.check()? {
+            Address::SevenBit(address) => (u16::from(address), 0),
+            Address::TenBit(address) => (address, I2C_M_TEN),
+        };
+        let mut msgs = Vec::with_capacity(messages.len());
+        for message in messages.iter_mut() {
+            let (flags, len, buf) = match message {
+                // the kernel does not write into the buffer of a write message
+                Message::Write(buf) => (0, buf.len(), buf.as_ptr() as *mut u8),
+                Message::Read(buf) => (I2C_M_RD, buf.len(), buf.as_mut_ptr()),
+                Message::ReadBlock(buf) => {
+                    if buf.len() < SMBUS_BLOCK_MAX + 1 {
+                        return Err(Error::InvalidArgument("block read buffer is too small"));
+                    }
+                    // number of bytes that are received before the count is known
+                    buf[0] = 1;
+                    (I2C_M_RD | I2C_M_RECV_LEN, buf.len(), buf.as_mut_ptr())
+                }
+            };
+            if len > usize::from(u16::MAX) {
+                return Err(Error::InvalidArgument("i2c message is too long"));
+            }
+            msgs.push(I2cMsg {
+                addr,
+                flags: flags | address_flags,
+                len: len as u16,
+                buf,
+            });
+        }
+        let mut data = I2cRdwrIoctlData {
+            msgs: msgs.as_mut_ptr(),
+            nmsgs: msgs.len() as u32,
+        };
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_RDWR, &mut data) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Interface for an i2c slave on a bus of the Raspberry Pi.
+ *
+ * An I2cPi addresses one slave at a time. The address can be changed with `set_slave_address`. 7 and 10 bit addresses are supported.
+ *
+ * The `Read` and `Write` implementation do plain i2c reads and writes. `write_read` combines a write and a read with a repeated start, like most register reads need. The SMBus commands are emulated on top of plain i2c transactions.
+ *
+ * Any [`I2cTransport`] can be used instead of the kernel interface, e.g. to test a driver against a mocked bus.
+ *
+ * [`I2cTransport`]: ./trait.I2cTransport.html
+ */
+pub struct I2cPi<T: I2cTransport = I2cDevice> {
+    transport: T,
+    address: Address,
+}
+
+impl I2cPi<I2cDevice> {
+    /**
+     * Opens /dev/i2c-`bus` and addresses the given slave. The pins of the raspberry pi are connected to bus 1.
+     *
+     * # Errors
+     * Can return an error if the i2c device can't be opened or the address is out of range.
+     */
+    pub fn new(bus: u8, address: Address) -> Result<I2cPi<I2cDevice>> {
+        I2cPi::with_transport(I2cDevice::open(bus)?, address)
+    }
+}
+
+impl<T: I2cTransport> I2cPi<T> {
+    /**
+     * Creates an i2c interface on the given transport.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if the address is out of range.
+     */
+    pub fn with_transport(transport: T, address: Address) -> Result<I2cPi<T>> {
+        Ok(I2cPi {
+            transport,
+            address: address.check()?,
+        })
+    }
+
+    /**
+     * Addresses another slave with the following transactions.
+     */
+    pub fn set_slave_address(&mut self, address: Address) -> Result<()> {
+        self.address = address.check()?;
+        Ok(())
+    }
+
+    pub fn slave_address(&self) -> Address {
+        self.address
+    }
+
+    /**
+     * Executes the messages as one transaction with the current slave.
+     */
+    pub fn transfer(&mut self, messages: &mut [Message]) -> Result<()> {
+        self.transport.transfer(self.address, messages)
+    }
+
+    /**
+     * Writes `tx` and reads `rx` afterwards with a repeated start in between.
+     */
+    pub fn write_read(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        self.transfer(&mut [Message::Write(tx), Message::Read(rx)])
+    }
+
+    /**
+     * Sends only the address with the read/write bit as data (SMBus quick command).
+     */
+    pub fn smbus_quick(&mut self, read: bool) -> Result<()> {
+        if read {
+            self.transfer(&mut [Message::Read(&mut [])])
+        } else {
+            self.transfer(&mut [Message::Write(&[])])
+        }
+    }
+
+    /**
+     * Reads a single byte without a command (SMBus receive byte).
+     */
+    pub fn smbus_read_byte(&mut self) -> Result<u8> {
+        let mut data = [0_u8; 1];
+        self.transfer(&mut [Message::Read(&mut data)])?;
+        Ok(data[0])
+    }
+
+    /**
+     * Writes a single byte without a command (SMBus send byte).
+     */
+    pub fn smbus_write_byte(&mut self, value: u8) -> Result<()> {
+        self.transfer(&mut [Message::Write(&[value])])
+    }
+
+    /**
+     * Reads the byte of a register (SMBus read byte).
+     */
+    pub fn smbus_read_byte_data(&mut self, command: u8) -> Result<u8> {
+        let mut data = [0_u8; 1];
+        self.write_read(&[command], &mut data)?;
+        Ok(data[0])
+    }
+
+    /**
+     * Writes the byte of a register (SMBus write byte).
+     */
+    pub fn smbus_write_byte_data(&mut self, command: u8, value: u8) -> Result<()> {
+        self.transfer(&mut [Message::Write(&[command, value])])
+    }
+
+    /**
+     * Reads the little endian word of a register (SMBus read word).
+     */
+    pub fn smbus_read_word_data(&mut self, command: u8) -> Result<u16> {
+        let mut data = [0_u8; 2];
+        self.write_read(&[command], &mut data)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    /**
+     * Writes the little endian word of a register (SMBus write word).
+     */
+    pub fn smbus_write_word_data(&mut self, command: u8, value: u16) -> Result<()> {
+        let [low, high] = value.to_le_bytes();
+        self.transfer(&mut [Message::Write(&[command, low, high])])
+    }
+
+    /**
+     * Reads a block of up to 32 bytes whose length is sent by the slave (SMBus block read).
+     * The adapter has to support reads with a length given by the slave.
+     */
+    pub fn smbus_read_block_data(&mut self, command: u8) -> Result<Vec<u8>> {
+        let mut data = [0_u8; SMBUS_BLOCK_MAX + 1];
+        self.transfer(&mut [Message::Write(&[command]), Message::ReadBlock(&mut data)])?;
+        let count = usize::from(data[0]);
+        if count > SMBUS_BLOCK_MAX {
+            return Err(Error::Protocol("slave sent a block longer than 32 bytes"));
+        }
+        Ok(data[1..=count].to_vec())
+    }
+
+    /**
+     * Writes a block of up to 32 bytes preceded by its length (SMBus block write).
+     */
+    pub fn smbus_write_block_data(&mut self, command: u8, block: &[u8]) -> Result<()> {
+        if block.len() > SMBUS_BLOCK_MAX {
+            return Err(Error::InvalidArgument(
+                "SMBus blocks can't be longer than 32 bytes",
+            ));
+        }
+        let mut data = Vec::with_capacity(block.len() + 2);
+        data.push(command);
+        data.push(block.len() as u8);
+        data.extend_from_slice(block);
+        self.transfer(&mut [Message::Write(&data)])
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+impl<T: I2cTransport> Read for I2cPi<T> {
+    /**
+     * Fills buf with bytes from the current slave in one transaction.
+     */
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.transfer(&mut [Message::Read(buf)])?;
+        Ok(buf.len())
+    }
+}
+
+impl<T: I2cTransport> Write for I2cPi<T> {
+    /**
+     * Writes buf to the current slave in one transaction.
+     */
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transfer(&mut [Message::Write(buf)])?;
+        Ok(buf.len())
+    }
+
+    /**
+     * Does nothing, every write is transmitted immediately.
+     */
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// First address that is probed by `scan`, the addresses below are reserved
+pub const SCAN_FIRST: u8 = 0x08;
+/// Last address that is probed by `scan`, the addresses above are reserved
+pub const SCAN_LAST: u8 = 0x77;
+
+/**
+ * Checks if a slave acknowledges the given 7 bit address.
+ *
+ * Like i2cdetect, addresses in 0x30-0x37 and 0x50-0x5F are probed with a read byte, because a quick write can corrupt some eeproms. All other addresses are probed with a quick write, because a read can lock up some write only chips.
+ *
+ * # Errors
+ * Only a missing acknowledge (`ENXIO`, `EREMOTEIO` or `ETIMEDOUT`) means that there is no slave. Other errors, e.g. of an adapter that does not support the probe, are returned.
+ */
+pub fn probe<T: I2cTransport>(transport: &mut T, address: u8) -> Result<bool> {
+    let read = matches!(address, 0x30..=0x37 | 0x50..=0x5F);
+    let address = Address::SevenBit(address);
+    let result = if read {
+        transport.transfer(address, &mut [Message::Read(&mut [0_u8; 1])])
+    } else {
+        transport.transfer(address, &mut [Message::Write(&[])])
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(Error::Io(ref error))
+            if matches!(
+                error.raw_os_error(),
+                Some(libc::ENXIO) | Some(libc::EREMOTEIO) | Some(libc::ETIMEDOUT)
+            ) =>
+        {
+            Ok(false)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/**
+ * Probes every valid 7 bit address from `SCAN_FIRST` to `SCAN_LAST` and returns the addresses that responded.
+ *
+ * # Errors
+ * Returns the first error of `probe` that is not a missing acknowledge.
+ */
+pub fn scan<T: I2cTransport>(transport: &mut T) -> Result<Vec<u8>> {
+    let mut found = Vec::new();
+    for address in SCAN_FIRST..=SCAN_LAST {
+        if probe(transport, address)? {
+            found.push(address);
+        }
+    }
+    Ok(found)
+}
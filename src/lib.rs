@@ -27,7 +27,14 @@ See the documentation for the [`SerialPi`] and [`GPIO`] structs for more details
 [`SerialPi`]: ./serial/struct.SerialPi.html
 [`GPIO`]: ./gpio/struct.GPIO.html
 
-There is also an interface to read out the [revision codes](https://www.raspberrypi.org/documentation/hardware/raspberrypi/revision-codes/README.md) in /proc/cpuinfo for programmatic use.
+Failures are reported with the [`Error`] enum of the crate. It converts into `io::Error` for code that works with `io::Result`.
+
+[`Error`]: ./error/enum.Error.html
+
+There is also an interface to read out the [revision codes](https://www.raspberrypi.org/documentation/hardware/raspberrypi/revision-codes/README.md), serial number and model of the board in /proc/cpuinfo and the device tree for programmatic use.
+
+The optional `async` feature adds wrappers to use the spi bus and gpio interrupts with [tokio](https://tokio.rs).
+The optional `serde` feature makes data types like the servo calibration and the board info serializable.
 
 # Installation
 To compile a raspberry pi program you need to prepare a cross compiler for rust
@@ -43,7 +50,7 @@ Build for the Raspberry with `cargo build --target=arm-unknown-linux-gnueabihf`
 
 # Example
 
-```
+```no_run
 extern crate rustpi_io;
 use rustpi_io::gpio::{GPIOData, GPIOMode, GPIO};
 
@@ -66,9 +73,26 @@ fn main() {
 ```
 */
 
+extern crate libc;
 extern crate spidev;
 
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod error;
 mod globals;
 pub mod gpio;
+pub mod i2c;
+pub mod modbus;
+pub mod onewire;
 pub mod pi;
+pub mod pwm;
+mod read_buffer;
 pub mod serial;
+pub mod servo;
+pub mod soft_i2c;
+pub mod soft_pwm;
+pub mod soft_spi;
+pub mod spi_bus;
+pub mod uart;
+
+pub use crate::error::{Error, Result};
@@ -0,0 +1,499 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! Modbus RTU master for serial lines like the uart.
+//!
+
+use crate::error::{Error, Result};
+use crate::uart::Uart;
+use std::error;
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const READ_COILS: u8 = 0x01;
+const READ_DISCRETE_INPUTS: u8 = 0x02;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_COILS: u8 = 0x0F;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+/// Set in the function code of an exception response
+const EXCEPTION_FLAG: u8 = 0x80;
+/// Slave address of a broadcast that no slave answers
+pub const BROADCAST: u8 = 0;
+
+/**
+ * The exception codes a slave can answer with.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExceptionCode {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    Acknowledge,
+    ServerDeviceBusy,
+    MemoryParityError,
+    GatewayPathUnavailable,
+    GatewayTargetFailedToRespond,
+    Unknown(u8),
+}
+
+impl ExceptionCode {
+    pub fn from_code(code: u8) -> ExceptionCode {
+        match code {
+            0x01 => ExceptionCode::IllegalFunction,
+            0x02 => ExceptionCode::IllegalDataAddress,
+            0x03 => ExceptionCode::IllegalDataValue,
+            0x04 => ExceptionCode::ServerDeviceFailure,
+            0x05 => ExceptionCode::Acknowledge,
+            0x06 => ExceptionCode::ServerDeviceBusy,
+            0x08 => ExceptionCode::MemoryParityError,
+            0x0A => ExceptionCode::GatewayPathUnavailable,
+            0x0B => ExceptionCode::GatewayTargetFailedToRespond,
+            code => ExceptionCode::Unknown(code),
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            ExceptionCode::IllegalFunction => 0x01,
+            ExceptionCode::IllegalDataAddress => 0x02,
+            ExceptionCode::IllegalDataValue => 0x03,
+            ExceptionCode::ServerDeviceFailure => 0x04,
+            ExceptionCode::Acknowledge => 0x05,
+            ExceptionCode::ServerDeviceBusy => 0x06,
+            ExceptionCode::MemoryParityError => 0x08,
+            ExceptionCode::GatewayPathUnavailable => 0x0A,
+            ExceptionCode::GatewayTargetFailedToRespond => 0x0B,
+            ExceptionCode::Unknown(code) => code,
+        }
+    }
+}
+
+/**
+ * An exception response of a slave.
+ *
+ * A request returns it as `Error::ModbusException`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exception {
+    pub function: u8,
+    pub code: ExceptionCode,
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "modbus exception {:#04x} ({:?}) for function {:#04x}",
+            self.code.code(),
+            self.code,
+            self.function
+        )
+    }
+}
+
+impl error::Error for Exception {}
+
+/**
+ * Calculates the CRC-16 of a modbus frame. It is sent low byte first.
+ */
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+/**
+ * Returns the silence of 3.5 characters that separates frames.
+ * Above 19200 baud the fixed 1.75 ms of the specification are used.
+ */
+pub fn frame_silence(baud_rate: u32) -> Duration {
+    if baud_rate > 19200 || baud_rate == 0 {
+        Duration::from_micros(1750)
+    } else {
+        // 3.5 characters with 11 bits each
+        Duration::from_micros(38_500_000 / u64::from(baud_rate))
+    }
+}
+
+fn check_count(count: usize, max: usize) -> Result<u16> {
+    if count == 0 || count > max {
+        return Err(Error::InvalidArgument("invalid number of modbus items"));
+    }
+    Ok(count as u16)
+}
+
+/**
+ * A serial line a [`RtuMaster`] talks over.
+ *
+ * [`RtuMaster`]: ./struct.RtuMaster.html
+ */
+pub trait RtuLine: Read + Write {
+    /**
+     * Discards received bytes that have not been read yet.
+     */
+    fn clear_input(&mut self) -> Result<()>;
+}
+
+impl RtuLine for Uart {
+    fn clear_input(&mut self) -> Result<()> {
+        Uart::clear_input(self)
+    }
+}
+
+/**
+ * A Modbus RTU master (client).
+ *
+ * The master sends a request, waits for the response of the slave and checks it. Frames are separated by the silence of 3.5 characters.
+ * The line can be any [`RtuLine`], usually a [`Uart`]. Reads of the line need a timeout, e.g. the read timeout of the uart, which is the response timeout of the master.
+ *
+ * Requests that time out or get a corrupted response are retried. Before a retry the master waits for the frame silence and discards the received bytes, so a late response to the failed attempt is not mistaken for the response to the retry. Exception responses are returned as `Error::ModbusException` with the [`Exception`] of the slave.
+ *
+ * [`RtuLine`]: ./trait.RtuLine.html
+ * [`Uart`]: ../uart/struct.Uart.html
+ * [`Exception`]: ./struct.Exception.html
+ */
+pub struct RtuMaster<T: RtuLine = Uart> {
+    line: T,
+    silence: Duration,
+    retries: u32,
+    last_frame: Option<Instant>,
+}
+
+impl RtuMaster<Uart> {
+    /**
+     * Creates a master on the uart and derives the frame silence from its baud rate.
+     */
+    pub fn with_uart(uart: Uart) -> RtuMaster<Uart> {
+        let baud_rate = uart.config().baud_rate;
+        RtuMaster::new(uart, baud_rate)
+    }
+}
+
+impl<T: RtuLine> RtuMaster<T> {
+    /**
+     * Creates a master on the line that runs with the given baud rate. Failed requests are retried twice.
+     */
+    pub fn new(line: T, baud_rate: u32) -> RtuMaster<T> {
+        RtuMaster {
+            line,
+            silence: frame_silence(baud_rate),
+            retries: 2,
+            last_frame: None,
+        }
+    }
+
+    /**
+     * Sets how often a request is repeated after a timeout or a corrupted response.
+     */
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /**
+     * Overrides the silence between frames.
+     */
+    pub fn set_frame_silence(&mut self, silence: Duration) {
+        self.silence = silence;
+    }
+
+    pub fn frame_silence(&self) -> Duration {
+        self.silence
+    }
+
+    pub fn line(&self) -> &T {
+        &self.line
+    }
+
+    pub fn line_mut(&mut self) -> &mut T {
+        &mut self.line
+    }
+
+    pub fn into_inner(self) -> T {
+        self.line
+    }
+
+    /**
+     * Reads `count` coils (1 - 2000) starting at `address`.
+     */
+    pub fn read_coils(&mut self, slave: u8, address: u16, count: usize) -> Result<Vec<bool>> {
+        self.read_bits(slave, READ_COILS, address, count)
+    }
+
+    /**
+     * Reads `count` discrete inputs (1 - 2000) starting at `address`.
+     */
+    pub fn read_discrete_inputs(
+        &mut self,
+        slave: u8,
+        address: u16,
+        count: usize,
+    ) -> Result<Vec<bool>> {
+        self.read_bits(slave, READ_DISCRETE_INPUTS, address, count)
+    }
+
+    /**
+     * Reads `count` holding registers (1 - 125) starting at `address`.
+     */
+    pub fn read_holding_registers(
+        &mut self,
+        slave: u8,
+        address: u16,
+        count: usize,
+    ) -> Result<Vec<u16>> {
+        self.read_registers(slave, READ_HOLDING_REGISTERS, address, count)
+    }
+
+    /**
+     * Reads `count` input registers (1 - 125) starting at `address`.
+     */
+    pub fn read_input_registers(
+        &mut self,
+        slave: u8,
+        address: u16,
+        count: usize,
+    ) -> Result<Vec<u16>> {
+        self.read_registers(slave, READ_INPUT_REGISTERS, address, count)
+    }
+
+    pub fn write_single_coil(&mut self, slave: u8, address: u16, value: bool) -> Result<()> {
+        let value: u16 = if value { 0xFF00 } else { 0x0000 };
+        self.write_single(slave, WRITE_SINGLE_COIL, address, value)
+    }
+
+    pub fn write_single_register(&mut self, slave: u8, address: u16, value: u16) -> Result<()> {
+        self.write_single(slave, WRITE_SINGLE_REGISTER, address, value)
+    }
+
+    /**
+     * Writes 1 - 1968 coils starting at `address`.
+     */
+    pub fn write_multiple_coils(&mut self, slave: u8, address: u16, values: &[bool]) -> Result<()> {
+        let count = check_count(values.len(), 1968)?;
+        let mut data = vec![0_u8; values.len().div_ceil(8)];
+        for (index, &value) in values.iter().enumerate() {
+            if value {
+                data[index / 8] |= 1 << (index % 8);
+            }
+        }
+        self.write_multiple(slave, WRITE_MULTIPLE_COILS, address, count, &data)
+    }
+
+    /**
+     * Writes 1 - 123 registers starting at `address`.
+     */
+    pub fn write_multiple_registers(
+        &mut self,
+        slave: u8,
+        address: u16,
+        values: &[u16],
+    ) -> Result<()> {
+        let count = check_count(values.len(), 123)?;
+        let data: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect();
+        self.write_multiple(slave, WRITE_MULTIPLE_REGISTERS, address, count, &data)
+    }
+
+    fn read_bits(
+        &mut self,
+        slave: u8,
+        function: u8,
+        address: u16,
+        count: usize,
+    ) -> Result<Vec<bool>> {
+        let count = check_count(count, 2000)?;
+        let mut request = vec![slave, function];
+        request.extend_from_slice(&address.to_be_bytes());
+        request.extend_from_slice(&count.to_be_bytes());
+        let response = self.request(&request)?;
+        let count = usize::from(count);
+        if response.len() != 3 + count.div_ceil(8) || usize::from(response[2]) + 3 != response.len()
+        {
+            return Err(Error::Protocol("modbus response has a wrong length"));
+        }
+        Ok((0..count)
+            .map(|index| response[3 + index / 8] & (1 << (index % 8)) != 0)
+            .collect())
+    }
+
+    fn read_registers(
+        &mut self,
+        slave: u8,
+        function: u8,
+        address: u16,
+        count: usize,
+    ) -> Result<Vec<u16>> {
+        let count = check_count(count, 125)?;
+        let mut request = vec![slave, function];
+        request.extend_from_slice(&address.to_be_bytes());
+        request.extend_from_slice(&count.to_be_bytes());
+        let response = self.request(&request)?;
+        if response.len() != 3 + 2 * usize::from(count)
+            || usize::from(response[2]) + 3 != response.len()
+        {
+            return Err(Error::Protocol("modbus response has a wrong length"));
+        }
+        Ok(response[3..]
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect())
+    }
+
+    fn write_single(&mut self, slave: u8, function: u8, address: u16, value: u16) -> Result<()> {
+        let mut request = vec![slave, function];
+        request.extend_from_slice(&address.to_be_bytes());
+        request.extend_from_slice(&value.to_be_bytes());
+        let response = self.request(&request)?;
+        if slave != BROADCAST && response != request {
+            return Err(Error::Protocol("modbus response does not echo the request"));
+        }
+        Ok(())
+    }
+
+    fn write_multiple(
+        &mut self,
+        slave: u8,
+        function: u8,
+        address: u16,
+        count: u16,
+        data: &[u8],
+    ) -> Result<()> {
+        let mut request = vec![slave, function];
+        request.extend_from_slice(&address.to_be_bytes());
+        request.extend_from_slice(&count.to_be_bytes());
+        request.push(data.len() as u8);
+        request.extend_from_slice(data);
+        let response = self.request(&request)?;
+        if slave != BROADCAST && response[..] != request[..6] {
+            return Err(Error::Protocol("modbus response does not echo the request"));
+        }
+        Ok(())
+    }
+
+    /**
+     * Sends the request and returns the response without the crc. Retries after timeouts and corrupted responses.
+     */
+    fn request(&mut self, request: &[u8]) -> Result<Vec<u8>> {
+        if request[0] == BROADCAST && request[1] <= READ_INPUT_REGISTERS {
+            return Err(Error::InvalidArgument("read requests can't be broadcast"));
+        }
+        let mut frame = request.to_vec();
+        frame.extend_from_slice(&crc16(request).to_le_bytes());
+        let mut attempt = 0;
+        loop {
+            let result = self.send(&frame).and_then(|_| {
+                if request[0] == BROADCAST {
+                    Ok(Vec::new())
+                } else {
+                    self.receive(request[0], request[1])
+                }
+            });
+            self.last_frame = Some(Instant::now());
+            match result {
+                Err(ref error) if attempt < self.retries && Self::retryable(error) => {
+                    attempt += 1;
+                    thread::sleep(self.silence);
+                    self.line.clear_input()?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn retryable(error: &Error) -> bool {
+        match error {
+            Error::Timeout(_) | Error::Protocol(_) => true,
+            Error::Io(error) => matches!(
+                error.kind(),
+                ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+
+    fn send(&mut self, frame: &[u8]) -> Result<()> {
+        if let Some(last_frame) = self.last_frame {
+            let elapsed = last_frame.elapsed();
+            if elapsed < self.silence {
+                thread::sleep(self.silence - elapsed);
+            }
+        }
+        self.line.write_all(frame)?;
+        self.line.flush()?;
+        Ok(())
+    }
+
+    fn receive(&mut self, slave: u8, function: u8) -> Result<Vec<u8>> {
+        let mut response = vec![0_u8; 3];
+        self.line.read_exact(&mut response)?;
+        let remaining = if response[1] == function | EXCEPTION_FLAG {
+            2
+        } else {
+            match function {
+                READ_COILS
+                | READ_DISCRETE_INPUTS
+                | READ_HOLDING_REGISTERS
+                | READ_INPUT_REGISTERS => usize::from(response[2]) + 2,
+                _ => 5,
+            }
+        };
+        let start = response.len();
+        response.resize(start + remaining, 0);
+        self.line.read_exact(&mut response[start..])?;
+        let length = response.len() - 2;
+        if crc16(&response[..length]).to_le_bytes() != response[length..] {
+            return Err(Error::Protocol("modbus response has a wrong crc"));
+        }
+        response.truncate(length);
+        if response[0] != slave {
+            return Err(Error::Protocol("modbus response from the wrong slave"));
+        }
+        if response[1] == function | EXCEPTION_FLAG {
+            return Err(Error::ModbusException(Exception {
+                function,
+                code: ExceptionCode::from_code(response[2]),
+            }));
+        }
+        if response[1] != function {
+            return Err(Error::Protocol("modbus response to the wrong function"));
+        }
+        Ok(response)
+    }
+}
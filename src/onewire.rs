@@ -0,0 +1,224 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! 1-Wire devices through the system interface under /sys/bus/w1/devices/.
+//!
+//! The bus has to be enabled with a device tree overlay first, e.g. `dtoverlay=w1-gpio` in /boot/config.txt.
+//!
+
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::globals::W1_PATH;
+
+/// Family code of the DS18B20 temperature sensor
+pub const FAMILY_DS18B20: u8 = 0x28;
+
+/// The id of a 1-Wire slave, formatted like the kernel names the device directories, e.g. `28-0316a2793dff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeviceId {
+    /// The family code of the device type
+    pub family: u8,
+    /// The 48 bit serial number
+    pub serial: u64,
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}-{:012x}", self.family, self.serial)
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = Error;
+
+    fn from_str(id: &str) -> Result<Self> {
+        let invalid = || Error::parse("1-wire device id", id);
+        let (family, serial) = id.split_once('-').ok_or_else(invalid)?;
+        if family.len() != 2 || serial.len() != 12 {
+            return Err(invalid());
+        }
+        Ok(DeviceId {
+            family: u8::from_str_radix(family, 16).map_err(|_| invalid())?,
+            serial: u64::from_str_radix(serial, 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// The resolution of a DS18B20 conversion. Higher resolutions need more time, from 94 ms for 9 bits to 750 ms for 12 bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Bits9 = 9,
+    Bits10 = 10,
+    Bits11 = 11,
+    Bits12 = 12,
+}
+
+impl Resolution {
+    fn from_bits(bits: u64) -> Result<Self> {
+        match bits {
+            9 => Ok(Resolution::Bits9),
+            10 => Ok(Resolution::Bits10),
+            11 => Ok(Resolution::Bits11),
+            12 => Ok(Resolution::Bits12),
+            _ => Err(Error::parse("ds18b20 resolution", &bits.to_string())),
+        }
+    }
+}
+
+/// The 1-Wire buses of the system.
+pub struct OneWire {
+    root: PathBuf,
+}
+
+impl OneWire {
+    /// Calls with_sysfs_root() with /sys/bus/w1/devices/.
+    pub fn new() -> Result<Self> {
+        OneWire::with_sysfs_root(W1_PATH)
+    }
+
+    /// Uses the devices below the given root.
+    /// A different root than /sys/bus/w1/devices/ can be used to work on a fake tree.
+    /// Returns Error::DeviceNotFound if the root does not exist.
+    pub fn with_sysfs_root<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        // a missing root is usually a missing w1-gpio overlay in /boot/config.txt
+        if let Err(source) = fs::metadata(&root) {
+            return Err(Error::DeviceNotFound { path: root, source });
+        }
+        Ok(OneWire { root })
+    }
+
+    /// Returns the ids of all slaves the kernel found on the buses, sorted by family and serial number.
+    /// The bus masters are skipped.
+    pub fn devices(&self) -> Result<Vec<DeviceId>> {
+        let mut devices = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            if let Some(Ok(id)) = entry?.file_name().to_str().map(DeviceId::from_str) {
+                devices.push(id);
+            }
+        }
+        devices.sort();
+        Ok(devices)
+    }
+
+    /// Returns the ids of all slaves of the given family.
+    pub fn devices_of_family(&self, family: u8) -> Result<Vec<DeviceId>> {
+        Ok(self
+            .devices()?
+            .into_iter()
+            .filter(|id| id.family == family)
+            .collect())
+    }
+
+    /// Returns the DS18B20 temperature sensor with the given id.
+    /// Returns an Error if the device is no DS18B20 or is not on a bus.
+    pub fn ds18b20(&self, id: DeviceId) -> Result<Ds18b20> {
+        if id.family != FAMILY_DS18B20 {
+            return Err(Error::InvalidArgument("the device is no ds18b20"));
+        }
+        let path = self.root.join(id.to_string());
+        if let Err(source) = fs::metadata(&path) {
+            return Err(Error::DeviceNotFound { path, source });
+        }
+        Ok(Ds18b20 { path, id })
+    }
+}
+
+/// A DS18B20 temperature sensor, read through the w1_therm driver.
+pub struct Ds18b20 {
+    path: PathBuf,
+    id: DeviceId,
+}
+
+impl Ds18b20 {
+    pub fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    /// Starts a conversion and returns the temperature in degree celsius.
+    /// Returns Error::Protocol if the kernel reports a crc error for the transfer.
+    pub fn temperature(&self) -> Result<f64> {
+        Ok(self.temperature_millicelsius()? as f64 / 1000.0)
+    }
+
+    /// Starts a conversion and returns the temperature in thousandths of a degree celsius.
+    /// The output of w1_slave has two lines: The scratchpad with the result of the crc check
+    /// (`... : crc=57 YES`) and the scratchpad with the temperature (`... t=23125`).
+    pub fn temperature_millicelsius(&self) -> Result<i32> {
+        let output = fs::read_to_string(self.path.join("w1_slave"))?;
+        let mut lines = output.lines();
+        let crc_line = lines.next().unwrap_or("");
+        if !crc_line.trim_end().ends_with("YES") {
+            return Err(Error::Protocol("crc check of the ds18b20 failed"));
+        }
+        let temperature = lines
+            .next()
+            .and_then(|line| line.rsplit_once("t="))
+            .ok_or_else(|| Error::parse("ds18b20 output", &output))?
+            .1;
+        temperature
+            .trim()
+            .parse()
+            .map_err(|_| Error::parse("ds18b20 temperature", temperature))
+    }
+
+    /// Sets the resolution of the following conversions.
+    /// Uses the resolution attribute of newer kernels and falls back to writing w1_slave.
+    pub fn set_resolution(&self, resolution: Resolution) -> Result<()> {
+        let attribute = if self.path.join("resolution").exists() {
+            "resolution"
+        } else {
+            "w1_slave"
+        };
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(self.path.join(attribute))?;
+        file.write_all((resolution as u8).to_string().as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the resolution of the conversions.
+    /// Uses the resolution attribute of newer kernels and falls back to the configuration register in the scratchpad.
+    pub fn resolution(&self) -> Result<Resolution> {
+        if let Ok(resolution) = fs::read_to_string(self.path.join("resolution")) {
+            return Resolution::from_bits(
+                resolution
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::parse("ds18b20 resolution", &resolution))?,
+            );
+        }
+        let output = fs::read_to_string(self.path.join("w1_slave"))?;
+        // the fifth byte of the scratchpad is the configuration register with the resolution in bit 5 and 6
+        let config = output
+            .split_whitespace()
+            .nth(4)
+            .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            .ok_or_else(|| Error::parse("ds18b20 output", &output))?;
+        Resolution::from_bits(9 + u64::from((config >> 5) & 0b11))
+    }
+}
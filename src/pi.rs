@@ -21,15 +21,17 @@
 //! Based on https://www.raspberrypi.org/documentation/hardware/raspberrypi/revision-codes/README.md
 //!
 
-use globals::RASPI_INFO_PATH;
+use crate::error::{Error, Result};
+use crate::globals::{PROC_PATH, RASPI_INFO_PATH};
 use std::fmt;
+use std::fs;
 use std::fs::File;
-use std::io::Error;
-use std::io::ErrorKind;
 use std::io::Read;
-use std::io::Result;
+use std::path::Path;
+use std::str::FromStr;
 
 #[derive(Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RevisionStyle {
     Old = 0,
     New = 1,
@@ -55,10 +57,16 @@ impl fmt::Display for RevisionStyle {
 }
 
 #[derive(Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MemorySize {
-    MB256 = 0,
-    MB512 = 1,
-    MB1024 = 2,
+    MB256,
+    MB512,
+    MB1024,
+    MB2048,
+    MB4096,
+    MB8192,
+    MB16384,
+    Unknown(u32),
 }
 
 impl fmt::Display for MemorySize {
@@ -67,27 +75,43 @@ impl fmt::Display for MemorySize {
             MemorySize::MB256 => write!(f, "256MB"),
             MemorySize::MB512 => write!(f, "512MB"),
             MemorySize::MB1024 => write!(f, "1024MB"),
+            MemorySize::MB2048 => write!(f, "2048MB"),
+            MemorySize::MB4096 => write!(f, "4096MB"),
+            MemorySize::MB8192 => write!(f, "8192MB"),
+            MemorySize::MB16384 => write!(f, "16384MB"),
+            MemorySize::Unknown(number) => write!(f, "unknown({})", number),
         }
     }
 }
 
 impl MemorySize {
-    pub fn from(number: u32) -> Option<MemorySize> {
+    pub fn from(number: u32) -> MemorySize {
         match number {
-            0 => Some(MemorySize::MB256),
-            1 => Some(MemorySize::MB512),
-            2 => Some(MemorySize::MB1024),
-            _ => None,
+            0 => MemorySize::MB256,
+            1 => MemorySize::MB512,
+            2 => MemorySize::MB1024,
+            3 => MemorySize::MB2048,
+            4 => MemorySize::MB4096,
+            5 => MemorySize::MB8192,
+            6 => MemorySize::MB16384,
+            _ => MemorySize::Unknown(number),
         }
     }
 }
 
+/// The manufacturer of the board.
+/// The revision code specification has no code for Sony China, such boards are reported with the code they carry.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Manufacturer {
-    SonyUK = 0,
-    Egoman = 1,
-    Embest = 2,
-    SonyJapan = 3,
+    SonyUK,
+    Egoman,
+    Embest,
+    SonyJapan,
+    Stadium,
+    /// Only used by old style revision codes
+    Qisda,
+    Unknown(u32),
 }
 
 impl fmt::Display for Manufacturer {
@@ -97,27 +121,35 @@ impl fmt::Display for Manufacturer {
             Manufacturer::Egoman => write!(f, "Egoman"),
             Manufacturer::Embest => write!(f, "Embest"),
             Manufacturer::SonyJapan => write!(f, "SonyJapan"),
+            Manufacturer::Stadium => write!(f, "Stadium"),
+            Manufacturer::Qisda => write!(f, "Qisda"),
+            Manufacturer::Unknown(number) => write!(f, "unknown({})", number),
         }
     }
 }
 
 impl Manufacturer {
-    pub fn from(number: u32) -> Option<Manufacturer> {
+    pub fn from(number: u32) -> Manufacturer {
         match number {
-            0 => Some(Manufacturer::SonyUK),
-            1 => Some(Manufacturer::Egoman),
-            2 => Some(Manufacturer::Embest),
-            3 => Some(Manufacturer::SonyJapan),
-            _ => None,
+            0 => Manufacturer::SonyUK,
+            1 => Manufacturer::Egoman,
+            2 | 4 => Manufacturer::Embest,
+            3 => Manufacturer::SonyJapan,
+            5 => Manufacturer::Stadium,
+            _ => Manufacturer::Unknown(number),
         }
     }
 }
 
 #[derive(Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Processor {
-    BCM2835 = 0,
-    BCM2836 = 1,
-    BCM2837 = 2,
+    BCM2835,
+    BCM2836,
+    BCM2837,
+    BCM2711,
+    BCM2712,
+    Unknown(u32),
 }
 
 impl fmt::Display for Processor {
@@ -126,34 +158,53 @@ impl fmt::Display for Processor {
             Processor::BCM2835 => write!(f, "BCM2835"),
             Processor::BCM2836 => write!(f, "BCM2836"),
             Processor::BCM2837 => write!(f, "BCM2837"),
+            Processor::BCM2711 => write!(f, "BCM2711"),
+            Processor::BCM2712 => write!(f, "BCM2712"),
+            Processor::Unknown(number) => write!(f, "unknown({})", number),
         }
     }
 }
 
 impl Processor {
-    pub fn from(number: u32) -> Option<Processor> {
+    pub fn from(number: u32) -> Processor {
         match number {
-            0 => Some(Processor::BCM2835),
-            1 => Some(Processor::BCM2836),
-            2 => Some(Processor::BCM2837),
-            _ => None,
+            0 => Processor::BCM2835,
+            1 => Processor::BCM2836,
+            2 => Processor::BCM2837,
+            3 => Processor::BCM2711,
+            4 => Processor::BCM2712,
+            _ => Processor::Unknown(number),
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
-    A = 0,
-    B = 1,
-    APlus = 2,
-    BPlus = 3,
-    B2 = 4,
-    Alpha = 5,
-    CM1 = 6,
-    B3 = 8,
-    Zero = 9,
-    CM3 = 10,   //a
-    ZeroW = 12, //c
+    A,
+    B,
+    APlus,
+    BPlus,
+    B2,
+    Alpha,
+    CM1,
+    B3,
+    Zero,
+    CM3,
+    ZeroW,
+    B3Plus,
+    A3Plus,
+    CM3Plus,
+    B4,
+    Zero2W,
+    Pi400,
+    CM4,
+    CM4S,
+    B5,
+    CM5,
+    Pi500,
+    CM5Lite,
+    Unknown(u32),
 }
 
 impl fmt::Display for Type {
@@ -170,57 +221,276 @@ impl fmt::Display for Type {
             Type::Zero => write!(f, "Zero"),
             Type::CM3 => write!(f, "CM3"),
             Type::ZeroW => write!(f, "ZeroW"),
+            Type::B3Plus => write!(f, "B3Plus"),
+            Type::A3Plus => write!(f, "A3Plus"),
+            Type::CM3Plus => write!(f, "CM3Plus"),
+            Type::B4 => write!(f, "B4"),
+            Type::Zero2W => write!(f, "Zero2W"),
+            Type::Pi400 => write!(f, "Pi400"),
+            Type::CM4 => write!(f, "CM4"),
+            Type::CM4S => write!(f, "CM4S"),
+            Type::B5 => write!(f, "B5"),
+            Type::CM5 => write!(f, "CM5"),
+            Type::Pi500 => write!(f, "Pi500"),
+            Type::CM5Lite => write!(f, "CM5Lite"),
+            Type::Unknown(number) => write!(f, "unknown({})", number),
         }
     }
 }
 
 impl Type {
-    pub fn from(number: u32) -> Option<Type> {
+    pub fn from(number: u32) -> Type {
         match number {
-            0 => Some(Type::A),
-            1 => Some(Type::B),
-            2 => Some(Type::APlus),
-            3 => Some(Type::BPlus),
-            4 => Some(Type::B2),
-            5 => Some(Type::Alpha),
-            6 => Some(Type::CM1),
-            8 => Some(Type::B3),
-            9 => Some(Type::Zero),
-            10 => Some(Type::CM3),
-            12 => Some(Type::ZeroW),
-            _ => None,
+            0x00 => Type::A,
+            0x01 => Type::B,
+            0x02 => Type::APlus,
+            0x03 => Type::BPlus,
+            0x04 => Type::B2,
+            0x05 => Type::Alpha,
+            0x06 => Type::CM1,
+            0x08 => Type::B3,
+            0x09 => Type::Zero,
+            0x0a => Type::CM3,
+            0x0c => Type::ZeroW,
+            0x0d => Type::B3Plus,
+            0x0e => Type::A3Plus,
+            0x10 => Type::CM3Plus,
+            0x11 => Type::B4,
+            0x12 => Type::Zero2W,
+            0x13 => Type::Pi400,
+            0x14 => Type::CM4,
+            0x15 => Type::CM4S,
+            0x17 => Type::B5,
+            0x18 => Type::CM5,
+            0x19 => Type::Pi500,
+            0x1a => Type::CM5Lite,
+            _ => Type::Unknown(number),
         }
     }
 }
 
+/// The decoded revision code of a raspberry pi.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RaspberryInfo {
+    /// The raw revision code
+    pub revision: u32,
+    pub style: RevisionStyle,
+    pub memory: MemorySize,
+    pub manufacturer: Manufacturer,
+    pub processor: Processor,
+    pub model: Type,
+    /// x for pcb revision 1.x. Revision 2.0 of the first boards is 16, the major version is stored above the minor one.
+    /// So values of new style revision codes are the same as in the code.
+    pub pcb_revision: u32,
+    /// The board has been overvolted
+    pub warranty_void: bool,
+    pub otp_program_disabled: bool,
+    pub otp_read_disabled: bool,
+    pub overvoltage_disallowed: bool,
+}
+
+impl fmt::Display for RaspberryInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} rev {}.{}, {}, {}, {} ({:x})",
+            self.model,
+            (self.pcb_revision >> 4) + 1,
+            self.pcb_revision & 0b1111u32,
+            self.memory,
+            self.processor,
+            self.manufacturer,
+            self.revision
+        )
+    }
+}
+
+impl FromStr for RaspberryInfo {
+    type Err = Error;
+
+    /// Decodes a hexadecimal revision code like "a02082".
+    fn from_str(revision: &str) -> Result<Self> {
+        let revision = revision.trim();
+        decode_revision(
+            u32::from_str_radix(revision.trim_start_matches("0x"), 16)
+                .map_err(|_| Error::parse("revision code", revision))?,
+        )
+    }
+}
+
 /// A function to extract information about the pi's hardware.
 /// The /proc/cpuinfo file is converted to progamatically usable data.
-/// The last element of the tuple is the revision of the raspberry model.
-pub fn get_raspberry_info() -> Result<(MemorySize, Manufacturer, Processor, Type, u32)> {
-    //open file and read data
-    let mut file = File::open(RASPI_INFO_PATH).unwrap();
-    let mut data = String::new();
-    file.read_to_string(&mut data).unwrap();
-    //parse
-    let position = data.rfind("Revision").unwrap();
-    let (_, revision_line) = data.split_at(position);
-    let mut iterator = revision_line.split_whitespace();
-    iterator.next();
-    iterator.next();
-    let revision_string = iterator.next().unwrap();
-    //convert to integer
-    let revision_int = u32::from_str_radix(revision_string, 16).unwrap();
-    if RevisionStyle::from((revision_int >> 23) & 0b1u32).unwrap() == RevisionStyle::Old {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            "Found old revision style which is not supported yet",
-        ));
+/// Returns an Error if the file can't be read or the revision code is missing, malformed or an unknown old style code.
+pub fn get_raspberry_info() -> Result<RaspberryInfo> {
+    let file = File::open(RASPI_INFO_PATH).map_err(|source| Error::DeviceNotFound {
+        path: RASPI_INFO_PATH.into(),
+        source,
+    })?;
+    raspberry_info_from_reader(file)
+}
+
+/// Like get_raspberry_info() but reads the cpuinfo text from any reader.
+pub fn raspberry_info_from_reader<R: Read>(mut reader: R) -> Result<RaspberryInfo> {
+    let mut cpuinfo = String::new();
+    reader.read_to_string(&mut cpuinfo)?;
+    raspberry_info_from_str(&cpuinfo)
+}
+
+/// Like get_raspberry_info() but parses the given cpuinfo text.
+pub fn raspberry_info_from_str(cpuinfo: &str) -> Result<RaspberryInfo> {
+    decode_revision(parse_revision(cpuinfo)?)
+}
+
+/// Extracts the revision code from the "Revision" line of the cpuinfo text.
+/// Returns Error::MissingField if there is no such line and Error::Parse if the code is not hexadecimal.
+pub fn parse_revision(cpuinfo: &str) -> Result<u32> {
+    let revision =
+        cpuinfo_field(cpuinfo, "Revision").ok_or(Error::MissingField { field: "Revision" })?;
+    u32::from_str_radix(revision, 16).map_err(|_| Error::parse("revision code", revision))
+}
+
+/// Returns the value of the last line with the given key.
+fn cpuinfo_field<'a>(cpuinfo: &'a str, field: &str) -> Option<&'a str> {
+    cpuinfo
+        .lines()
+        .rev()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim() == field)
+        .map(|(_, value)| value.trim())
+}
+
+/// The revision code together with the information of the board that is not part of the code.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoardInfo {
+    pub revision: RaspberryInfo,
+    /// The unique serial number like "00000000deadbeef"
+    pub serial: Option<String>,
+    /// The Hardware line of /proc/cpuinfo like "BCM2835", 64-bit kernels omit it
+    pub hardware: Option<String>,
+    /// The model name like "Raspberry Pi 3 Model B Rev 1.2"
+    pub model: Option<String>,
+}
+
+/// Calls board_info_with_proc_root() with /proc/.
+pub fn get_board_info() -> Result<BoardInfo> {
+    board_info_with_proc_root(PROC_PATH)
+}
+
+/// Reads cpuinfo and the device tree below the given root.
+/// A different root than /proc/ can be used to work on a fake tree.
+/// 64-bit kernels have no Revision and Serial lines in cpuinfo, so the device tree is used for them.
+/// Model name and serial number of the device tree are preferred over the cpuinfo lines.
+/// Returns an Error if no revision code can be read or the code is malformed or an unknown old style code.
+pub fn board_info_with_proc_root<P: AsRef<Path>>(root: P) -> Result<BoardInfo> {
+    let root = root.as_ref();
+    let cpuinfo_path = root.join("cpuinfo");
+    let device_tree = root.join("device-tree");
+    let cpuinfo = fs::read_to_string(&cpuinfo_path).map_err(|source| Error::DeviceNotFound {
+        path: cpuinfo_path,
+        source,
+    });
+    let code = match cpuinfo {
+        Ok(ref cpuinfo) if cpuinfo_field(cpuinfo, "Revision").is_some() => parse_revision(cpuinfo)?,
+        // the device tree stores the code as big endian cell
+        _ => match fs::read(device_tree.join("system/linux,revision")) {
+            Ok(cell) if cell.len() == 4 => u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]),
+            _ => {
+                cpuinfo?;
+                return Err(Error::MissingField { field: "Revision" });
+            }
+        },
+    };
+    let cpuinfo = cpuinfo.unwrap_or_default();
+    let field = |key| cpuinfo_field(&cpuinfo, key).map(String::from);
+    Ok(BoardInfo {
+        revision: decode_revision(code)?,
+        serial: device_tree_string(&device_tree.join("serial-number")).or_else(|| field("Serial")),
+        hardware: field("Hardware"),
+        model: device_tree_string(&device_tree.join("model")).or_else(|| field("Model")),
+    })
+}
+
+/// Reads a null terminated string property of the device tree.
+fn device_tree_string(path: &Path) -> Option<String> {
+    let property = fs::read(path).ok()?;
+    let property = String::from_utf8_lossy(&property);
+    let property = property.trim_end_matches('\0').trim();
+    if property.is_empty() {
+        None
+    } else {
+        Some(property.to_string())
     }
-    Ok((
-        MemorySize::from((revision_int >> 20) & 0b111u32).unwrap(),
-        Manufacturer::from((revision_int >> 16) & 0b1111u32).unwrap(),
-        Processor::from((revision_int >> 12) & 0b1111u32).unwrap(),
-        Type::from((revision_int >> 4) & 0b11111111u32).unwrap(),
-        revision_int & 0b1111u32,
-    ))
+}
+
+/// Splits a revision code into its fields. Old style codes are looked up in a table.
+/// Unknown fields of new style codes are reported as Unknown variants.
+/// Returns Error::UnsupportedRevision if an old style code is unknown.
+pub fn decode_revision(revision: u32) -> Result<RaspberryInfo> {
+    let bit = |index: u32| (revision >> index) & 0b1u32 == 1;
+    let (memory, manufacturer, processor, model, pcb_revision, warranty_void, style) = if bit(23) {
+        (
+            MemorySize::from((revision >> 20) & 0b111u32),
+            Manufacturer::from((revision >> 16) & 0b1111u32),
+            Processor::from((revision >> 12) & 0b1111u32),
+            Type::from((revision >> 4) & 0b11111111u32),
+            revision & 0b1111u32,
+            bit(25),
+            RevisionStyle::New,
+        )
+    } else {
+        let (memory, manufacturer, model, pcb_revision) =
+            decode_old_revision(revision).ok_or(Error::UnsupportedRevision { revision })?;
+        (
+            memory,
+            manufacturer,
+            Processor::BCM2835,
+            model,
+            pcb_revision,
+            bit(24),
+            RevisionStyle::Old,
+        )
+    };
+    Ok(RaspberryInfo {
+        revision,
+        style,
+        memory,
+        manufacturer,
+        processor,
+        model,
+        pcb_revision,
+        warranty_void,
+        otp_read_disabled: bit(29),
+        otp_program_disabled: bit(30),
+        overvoltage_disallowed: bit(31),
+    })
+}
+
+/// Looks up an old style revision code of the first boards.
+/// The bits above the code mark boards that were overvolted (bit 24 voids the warranty).
+fn decode_old_revision(revision: u32) -> Option<(MemorySize, Manufacturer, Type, u32)> {
+    use self::Manufacturer::*;
+    use self::MemorySize::*;
+    use self::Type::*;
+    let (board, pcb_revision, memory, manufacturer) = match revision & 0xFF_FFFF {
+        0x02 | 0x03 => (B, 0x00, MB256, Egoman),
+        0x04 => (B, 0x10, MB256, SonyUK),
+        0x05 => (B, 0x10, MB256, Qisda),
+        0x06 => (B, 0x10, MB256, Egoman),
+        0x07 => (A, 0x10, MB256, Egoman),
+        0x08 => (A, 0x10, MB256, SonyUK),
+        0x09 => (A, 0x10, MB256, Qisda),
+        0x0d | 0x0f => (B, 0x10, MB512, Egoman),
+        0x0e => (B, 0x10, MB512, SonyUK),
+        0x10 => (BPlus, 0x02, MB512, SonyUK),
+        0x11 => (CM1, 0x00, MB512, SonyUK),
+        0x12 => (APlus, 0x01, MB256, SonyUK),
+        0x13 => (BPlus, 0x02, MB512, Embest),
+        0x14 => (CM1, 0x00, MB512, Embest),
+        // shipped with 256MB and 512MB
+        0x15 => (APlus, 0x01, MB256, Embest),
+        _ => return None,
+    };
+    Some((memory, manufacturer, board, pcb_revision))
 }
@@ -0,0 +1,272 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! Hardware pwm through the system interface under /sys/class/pwm/.
+//!
+//! The pwm channels have to be enabled with a device tree overlay first, e.g. `dtoverlay=pwm-2chan` in /boot/config.txt.
+//!
+
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::globals::PWM_PATH;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polarity {
+    Normal,
+    Inversed,
+}
+
+/// A software representation for a channel of a pwm chip.
+pub struct PWM {
+    root: PathBuf,
+    chip: u8,
+    channel: u8,
+}
+
+impl PWM {
+    /// Calls with_sysfs_root() with /sys/class/pwm/.
+    pub fn new(chip: u8, channel: u8) -> Result<Self> {
+        PWM::with_sysfs_root(PWM_PATH, chip, channel)
+    }
+
+    /// Initializes the pwm channel. Exports the channel with the pwmchipN/export file below the given root.
+    /// A different root than /sys/class/pwm/ can be used to work on a fake tree.
+    /// Returns Error::DeviceNotFound if the chip does not exist or an Error if the channel was already exported earlier
+    /// (inside or outside of the application)
+    pub fn with_sysfs_root<P: AsRef<Path>>(root: P, chip: u8, channel: u8) -> Result<Self> {
+        let root = root.as_ref().join(format!("pwmchip{}", chip));
+        {
+            // a missing chip is usually a missing pwm overlay in /boot/config.txt
+            let mut export = OpenOptions::new()
+                .write(true)
+                .open(root.join("export"))
+                .map_err(|source| Error::DeviceNotFound {
+                    path: root.clone(),
+                    source,
+                })?;
+            export.write_all(format!("{}", channel).as_bytes())?;
+        }
+        Ok(PWM {
+            root,
+            chip,
+            channel,
+        })
+    }
+
+    /// Returns the configured chip of the PWM struct.
+    pub fn chip_number(&self) -> u8 {
+        self.chip
+    }
+
+    /// Returns the configured channel of the PWM struct.
+    pub fn channel_number(&self) -> u8 {
+        self.channel
+    }
+
+    /// Sets the length of one pwm cycle.
+    /// The period can't be shorter than the current duty cycle.
+    pub fn set_period(&self, period: Duration) -> Result<()> {
+        self.write_attribute("period", period.as_nanos())
+    }
+
+    /// Reads the length of one pwm cycle.
+    pub fn period(&self) -> Result<Duration> {
+        Ok(Duration::from_nanos(self.read_attribute("period")?))
+    }
+
+    /// Sets the active time of one pwm cycle.
+    /// The duty cycle can't be longer than the period.
+    pub fn set_duty_cycle(&self, duty_cycle: Duration) -> Result<()> {
+        self.write_attribute("duty_cycle", duty_cycle.as_nanos())
+    }
+
+    /// Reads the active time of one pwm cycle.
+    pub fn duty_cycle(&self) -> Result<Duration> {
+        Ok(Duration::from_nanos(self.read_attribute("duty_cycle")?))
+    }
+
+    /// Sets the polarity of the signal. Most chips only allow this while the channel is disabled.
+    pub fn set_polarity(&self, polarity: Polarity) -> Result<()> {
+        self.write_attribute("polarity", polarity)
+    }
+
+    /// Reads the polarity of the signal.
+    /// Returns Error::Parse if a value other than "normal" or "inversed" is read
+    pub fn polarity(&self) -> Result<Polarity> {
+        match self.read_string("polarity")?.as_str() {
+            "normal" => Ok(Polarity::Normal),
+            "inversed" => Ok(Polarity::Inversed),
+            polarity => Err(Error::parse("pwm polarity", polarity)),
+        }
+    }
+
+    /// Starts the signal generation.
+    pub fn enable(&self) -> Result<()> {
+        self.write_attribute("enable", 1)
+    }
+
+    /// Stops the signal generation.
+    pub fn disable(&self) -> Result<()> {
+        self.write_attribute("enable", 0)
+    }
+
+    /// Returns true if the signal is generated.
+    pub fn is_enabled(&self) -> Result<bool> {
+        Ok(self.read_attribute("enable")? != 0)
+    }
+
+    /// Sets the period to match the frequency in Hz. The ratio of duty cycle and period is preserved.
+    /// Returns an Error if the frequency is not positive
+    pub fn set_frequency(&self, frequency: f64) -> Result<()> {
+        if frequency <= 0.0 || !frequency.is_finite() {
+            return Err(Error::InvalidArgument("frequency has to be positive"));
+        }
+        let ratio = self.duty_ratio()?;
+        let period = Duration::try_from_secs_f64(1.0 / frequency)
+            .map_err(|_| Error::InvalidArgument("frequency is too low"))?;
+        let duty_cycle = period.mul_f64(ratio);
+        // the duty cycle must never exceed the period, even in between the two writes
+        if period < self.duty_cycle()? {
+            self.set_duty_cycle(duty_cycle)?;
+            self.set_period(period)
+        } else {
+            self.set_period(period)?;
+            self.set_duty_cycle(duty_cycle)
+        }
+    }
+
+    /// Returns the frequency in Hz. Returns 0 if no period is set.
+    pub fn frequency(&self) -> Result<f64> {
+        let period = self.period()?;
+        if period == Duration::from_secs(0) {
+            return Ok(0.0);
+        }
+        Ok(1.0 / period.as_secs_f64())
+    }
+
+    /// Sets the duty cycle as ratio of the period. 0.0 is always off and 1.0 is always on.
+    /// Returns an Error if the ratio is not in this range
+    pub fn set_duty_ratio(&self, ratio: f64) -> Result<()> {
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(Error::InvalidArgument(
+                "duty ratio has to be between 0 and 1",
+            ));
+        }
+        self.set_duty_cycle(self.period()?.mul_f64(ratio))
+    }
+
+    /// Returns the duty cycle as ratio of the period. Returns 0 if no period is set.
+    pub fn duty_ratio(&self) -> Result<f64> {
+        let period = self.period()?;
+        if period == Duration::from_secs(0) {
+            return Ok(0.0);
+        }
+        Ok(self.duty_cycle()?.as_secs_f64() / period.as_secs_f64())
+    }
+
+    fn channel_path(&self, attribute: &str) -> PathBuf {
+        self.root
+            .join(format!("pwm{}", self.channel))
+            .join(attribute)
+    }
+
+    fn write_attribute<T: fmt::Display>(&self, attribute: &str, value: T) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(self.channel_path(attribute))?;
+        file.write_all(value.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    fn read_string(&self, attribute: &str) -> Result<String> {
+        Ok(fs::read_to_string(self.channel_path(attribute))?
+            .trim()
+            .to_string())
+    }
+
+    fn read_attribute(&self, attribute: &str) -> Result<u64> {
+        let value = self.read_string(attribute)?;
+        value
+            .parse()
+            .map_err(|_| Error::parse("pwm attribute", &value))
+    }
+}
+
+/// A source of a pwm signal. It is implemented by [`PWM`] and [`SoftPwm`],
+/// so drivers like [`Servo`] work with both.
+///
+/// [`PWM`]: ./struct.PWM.html
+/// [`SoftPwm`]: ../soft_pwm/struct.SoftPwm.html
+/// [`Servo`]: ../servo/struct.Servo.html
+pub trait PwmOutput {
+    /// Sets the length of one pwm cycle
+    fn set_period(&self, period: Duration) -> Result<()>;
+    /// Sets the active time of one pwm cycle
+    fn set_duty_cycle(&self, duty_cycle: Duration) -> Result<()>;
+    /// Starts the signal generation
+    fn enable(&self) -> Result<()>;
+}
+
+impl PwmOutput for PWM {
+    fn set_period(&self, period: Duration) -> Result<()> {
+        PWM::set_period(self, period)
+    }
+
+    fn set_duty_cycle(&self, duty_cycle: Duration) -> Result<()> {
+        PWM::set_duty_cycle(self, duty_cycle)
+    }
+
+    fn enable(&self) -> Result<()> {
+        PWM::enable(self)
+    }
+}
+
+/// Disables the channel and writes its number into pwmchipN/unexport.
+/// Failures are ignored, the channel may already be gone.
+impl Drop for PWM {
+    fn drop(&mut self) {
+        self.disable().unwrap_or(());
+        if let Ok(mut unexport) = OpenOptions::new()
+            .write(true)
+            .open(self.root.join("unexport"))
+        {
+            unexport
+                .write_all(format!("{}", self.channel).as_bytes())
+                .unwrap_or(());
+        }
+    }
+}
+
+///Writes "normal" or "inversed"
+impl fmt::Display for Polarity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Polarity::Normal => write!(f, "normal"),
+            Polarity::Inversed => write!(f, "inversed"),
+        }
+    }
+}
@@ -0,0 +1,357 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+use crate::error::{Error, Result};
+use crate::serial::OverflowPolicy;
+use std::io;
+use std::io::Read;
+
+/// The internal buffer of `SerialPi` for bytes that were received but not yet read.
+///
+/// It is a ring buffer on initialized memory, so the storage can be handed to any
+/// reader without exposing uninitialized bytes. If a reader fails, the buffer is
+/// left unchanged. The transport is only passed in as `Read`, so the buffering can
+/// be driven by a mocked device as well.
+pub(crate) struct ReadBuffer {
+    storage: Vec<u8>,
+    head: usize,
+    len: usize,
+    limit: usize,
+    overflow_policy: OverflowPolicy,
+}
+
+impl ReadBuffer {
+    pub fn with_capacity(capacity: usize) -> ReadBuffer {
+        ReadBuffer {
+            storage: vec![0; capacity],
+            head: 0,
+            len: 0,
+            limit: capacity,
+            overflow_policy: OverflowPolicy::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    /// Shrinks the storage to the buffered bytes or the desired capacity, whichever is bigger.
+    /// The desired capacity becomes the limit for the overflow policy.
+    pub fn shrink_to(&mut self, desired_capacity: usize) -> usize {
+        self.align();
+        let capacity = desired_capacity.max(self.len);
+        self.storage.truncate(capacity);
+        self.storage.resize(capacity, 0);
+        self.storage.shrink_to_fit();
+        self.limit = desired_capacity;
+        self.capacity()
+    }
+
+    /// Applies the overflow policy for `incoming` bytes that are about to be appended.
+    /// Fails for `OverflowPolicy::Error` if they do not fit.
+    pub fn reserve(&mut self, incoming: usize) -> Result<()> {
+        let free = self.limit.saturating_sub(self.len);
+        match self.overflow_policy {
+            OverflowPolicy::Grow => self.grow(incoming),
+            OverflowPolicy::DropOldest => {
+                if incoming > free {
+                    self.consume(incoming - free);
+                }
+            }
+            OverflowPolicy::Error => {
+                if incoming > free {
+                    return Err(Error::BufferFull);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends `data` to the buffer. Call `reserve` first to apply the overflow policy.
+    /// If the data is bigger than the limit of a bounded buffer only the newest bytes are kept.
+    pub fn extend(&mut self, data: &[u8]) {
+        let data = if self.overflow_policy != OverflowPolicy::Grow && data.len() > self.limit {
+            &data[data.len() - self.limit..]
+        } else {
+            data
+        };
+        if data.len() > self.capacity() - self.len {
+            self.grow(data.len());
+        }
+        let capacity = self.capacity();
+        let tail = (self.head + self.len) % capacity.max(1);
+        let first = data.len().min(capacity - tail);
+        self.storage[tail..tail + first].copy_from_slice(&data[..first]);
+        self.storage[..data.len() - first].copy_from_slice(&data[first..]);
+        self.len += data.len();
+    }
+
+    /// Moves the oldest bytes into `buf` and returns how many were moved.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let count = buf.len().min(self.len);
+        let first = count.min(self.capacity() - self.head);
+        buf[..first].copy_from_slice(&self.storage[self.head..self.head + first]);
+        buf[first..count].copy_from_slice(&self.storage[..count - first]);
+        self.consume(count);
+        count
+    }
+
+    /// Drops the oldest `amt` bytes.
+    pub fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.len);
+        self.len -= amt;
+        self.head = if self.len == 0 {
+            0
+        } else {
+            (self.head + amt) % self.capacity()
+        };
+    }
+
+    /// Returns all buffered bytes as one slice.
+    pub fn as_slice(&mut self) -> &[u8] {
+        if self.head + self.len > self.capacity() {
+            self.align();
+        }
+        &self.storage[self.head..self.head + self.len]
+    }
+
+    /// Appends as many bytes from `reader` as fit into the buffer, but not more than `max`, and returns the appended bytes.
+    /// A bounded buffer is filled up to its limit, a growing one up to its capacity.
+    /// Only whole words of `word_size` bytes are requested from the reader.
+    pub fn fill_from<R: Read>(
+        &mut self,
+        reader: &mut R,
+        max: usize,
+        word_size: usize,
+    ) -> io::Result<&mut [u8]> {
+        self.align();
+        let end = match self.overflow_policy {
+            OverflowPolicy::Grow => self.capacity(),
+            _ => self.limit.min(self.capacity()).max(self.len),
+        };
+        let free = end.min(self.len + max) - self.len;
+        let end = self.len + free - free % word_size;
+        let count = reader.read(&mut self.storage[self.len..end])?;
+        let start = self.len;
+        self.len += count;
+        Ok(&mut self.storage[start..start + count])
+    }
+
+    /// Moves the buffered bytes to the start of the storage.
+    fn align(&mut self) {
+        if self.head != 0 {
+            self.storage.rotate_left(self.head);
+            self.head = 0;
+        }
+    }
+
+    /// Makes room for at least `incoming` more bytes.
+    fn grow(&mut self, incoming: usize) {
+        if self.capacity() - self.len < incoming {
+            self.align();
+            let capacity = (self.len + incoming).max(2 * self.capacity());
+            self.storage.resize(capacity, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A device that returns increasing bytes and can be told to fail the next read.
+    struct MockDevice {
+        next: u8,
+        fail: bool,
+    }
+
+    impl MockDevice {
+        fn new() -> MockDevice {
+            MockDevice {
+                next: 0,
+                fail: false,
+            }
+        }
+    }
+
+    impl Read for MockDevice {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail {
+                self.fail = false;
+                // a failing transfer may have scribbled over the receive buffer
+                for byte in buf.iter_mut() {
+                    *byte = 0xEE;
+                }
+                return Err(io::Error::other("transfer failed"));
+            }
+            for byte in buf.iter_mut() {
+                *byte = self.next;
+                self.next = self.next.wrapping_add(1);
+            }
+            Ok(buf.len())
+        }
+    }
+
+    fn bounded(capacity: usize, overflow_policy: OverflowPolicy) -> ReadBuffer {
+        let mut buffer = ReadBuffer::with_capacity(capacity);
+        buffer.set_overflow_policy(overflow_policy);
+        buffer
+    }
+
+    fn wrapped() -> ReadBuffer {
+        let mut buffer = bounded(8, OverflowPolicy::Error);
+        buffer.extend(&[0, 1, 2, 3, 4, 5]);
+        let mut buf = [0; 4];
+        assert_eq!(buffer.read(&mut buf), 4);
+        assert_eq!(buf, [0, 1, 2, 3]);
+        buffer.reserve(6).unwrap();
+        buffer.extend(&[6, 7, 8, 9, 10, 11]);
+        // the bytes 8 to 11 are stored in front of the head
+        assert!(buffer.head + buffer.len > buffer.capacity());
+        buffer
+    }
+
+    #[test]
+    fn read_across_the_end_of_the_storage() {
+        let mut buffer = wrapped();
+        let mut buf = [0; 5];
+        assert_eq!(buffer.read(&mut buf), 5);
+        assert_eq!(buf, [4, 5, 6, 7, 8]);
+        assert_eq!(buffer.as_slice(), &[9, 10, 11]);
+        assert_eq!(buffer.capacity(), 8);
+    }
+
+    #[test]
+    fn as_slice_of_wrapped_buffer() {
+        let mut buffer = wrapped();
+        assert_eq!(buffer.as_slice(), &[4, 5, 6, 7, 8, 9, 10, 11]);
+        buffer.consume(3);
+        assert_eq!(buffer.as_slice(), &[7, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn interleaved_fill_consume_and_read() {
+        let mut device = MockDevice::new();
+        let mut buffer = bounded(8, OverflowPolicy::DropOldest);
+        assert_eq!(buffer.fill_from(&mut device, 3, 1).unwrap(), &[0, 1, 2]);
+        buffer.consume(1);
+        assert_eq!(
+            buffer.fill_from(&mut device, 16, 1).unwrap(),
+            &[3, 4, 5, 6, 7, 8]
+        );
+        assert_eq!(buffer.as_slice(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        // a full bounded buffer reads nothing
+        assert!(buffer.fill_from(&mut device, 16, 1).unwrap().is_empty());
+        let mut buf = [0; 3];
+        assert_eq!(buffer.read(&mut buf), 3);
+        assert_eq!(buf, [1, 2, 3]);
+        buffer.consume(2);
+        assert_eq!(
+            buffer.fill_from(&mut device, 16, 1).unwrap(),
+            &[9, 10, 11, 12, 13]
+        );
+        assert_eq!(buffer.as_slice(), &[6, 7, 8, 9, 10, 11, 12, 13]);
+        let mut buf = [0; 10];
+        assert_eq!(buffer.read(&mut buf), 8);
+        assert_eq!(&buf[..8], &[6, 7, 8, 9, 10, 11, 12, 13]);
+        assert!(buffer.as_slice().is_empty());
+    }
+
+    #[test]
+    fn fill_never_splits_a_word() {
+        let mut device = MockDevice::new();
+        let mut buffer = bounded(7, OverflowPolicy::Error);
+        assert_eq!(
+            buffer.fill_from(&mut device, 16, 2).unwrap(),
+            &[0, 1, 2, 3, 4, 5]
+        );
+        assert!(buffer.fill_from(&mut device, 16, 2).unwrap().is_empty());
+        buffer.consume(4);
+        assert_eq!(buffer.fill_from(&mut device, 3, 2).unwrap(), &[6, 7]);
+    }
+
+    #[test]
+    fn failed_fill_leaves_buffer_unchanged() {
+        let mut device = MockDevice::new();
+        let mut buffer = wrapped();
+        device.fail = true;
+        assert!(buffer.fill_from(&mut device, 16, 1).is_err());
+        assert_eq!(buffer.as_slice(), &[4, 5, 6, 7, 8, 9, 10, 11]);
+
+        let mut buffer = bounded(8, OverflowPolicy::DropOldest);
+        buffer.extend(&[1, 2, 3]);
+        buffer.consume(1);
+        device.fail = true;
+        assert!(buffer.fill_from(&mut device, 16, 1).is_err());
+        assert_eq!(buffer.as_slice(), &[2, 3]);
+        // the next read continues after the kept bytes
+        assert_eq!(buffer.fill_from(&mut device, 2, 1).unwrap(), &[0, 1]);
+        assert_eq!(buffer.as_slice(), &[2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest_bytes() {
+        let mut buffer = bounded(4, OverflowPolicy::DropOldest);
+        buffer.reserve(4).unwrap();
+        buffer.extend(&[0, 1, 2, 3]);
+        buffer.reserve(2).unwrap();
+        buffer.extend(&[4, 5]);
+        assert_eq!(buffer.as_slice(), &[2, 3, 4, 5]);
+        buffer.reserve(6).unwrap();
+        buffer.extend(&[6, 7, 8, 9, 10, 11]);
+        assert_eq!(buffer.as_slice(), &[8, 9, 10, 11]);
+        assert_eq!(buffer.capacity(), 4);
+    }
+
+    #[test]
+    fn error_policy_rejects_overflow() {
+        let mut buffer = bounded(4, OverflowPolicy::Error);
+        buffer.reserve(3).unwrap();
+        buffer.extend(&[0, 1, 2]);
+        match buffer.reserve(2) {
+            Err(Error::BufferFull) => {}
+            other => panic!("expected BufferFull, got {:?}", other),
+        }
+        assert_eq!(buffer.as_slice(), &[0, 1, 2]);
+        buffer.consume(1);
+        buffer.reserve(2).unwrap();
+        buffer.extend(&[3, 4]);
+        assert_eq!(buffer.as_slice(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn grow_keeps_all_bytes() {
+        let mut buffer = ReadBuffer::with_capacity(2);
+        buffer.extend(&[0, 1]);
+        buffer.consume(1);
+        buffer.reserve(5).unwrap();
+        buffer.extend(&[2, 3, 4, 5, 6]);
+        assert_eq!(buffer.as_slice(), &[1, 2, 3, 4, 5, 6]);
+        assert!(buffer.capacity() >= 6);
+        assert_eq!(buffer.shrink_to(2), 6);
+    }
+}
@@ -17,16 +17,27 @@
 // You should have received a copy of the GNU General Public License
 // along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
 
-use globals::{SPI_PATH0, SPI_PATH1};
+use crate::error::{Error, Result};
+use crate::globals::{SPIDEV_BUFSIZ_PATH, SPI_PATH0, SPI_PATH1};
+use crate::gpio::{GPIOData, GPIOMode, GPIO};
+use crate::read_buffer::ReadBuffer;
+use spidev::spidevioctl;
 use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+use std::fs;
 use std::io;
 use std::io::{BufRead, Read, Write};
-use std::io::{Error, ErrorKind};
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+/// Transfer limit of the spidev driver if the module parameter can't be read
+const DEFAULT_SPIDEV_BUFSIZ: usize = 4096;
 
 /**
  * Correspond to the SPI Chip Enable Pins on the raspberry pi.
  */
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Device {
     CE0 = 0,
     CE1 = 1,
@@ -51,7 +62,7 @@ Possible Speeds:
    15.2 kHz
    7629 Hz
 */
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Speed {
     Mhz125_0,
@@ -73,8 +84,8 @@ pub enum Speed {
 
 impl Speed {
     /// Converts the `Speed` variants into an integer representing the Hz value
-    fn to_int(&self) -> u32 {
-        match *self {
+    fn to_int(self) -> u32 {
+        match self {
             Speed::Mhz125_0 => 125_000_001,
             Speed::Mhz62_5 => 62_500_001,
             Speed::Mhz31_2 => 31_200_001,
@@ -99,7 +110,7 @@ The most common spi modes regulating the clock phase and polarity.
 Mode 0 seems to be the most used one and is set as default.
 See https://en.wikipedia.org/wiki/Serial_Peripheral_Interface_Bus#Clock_polarity_and_phase for an explanation
 */
-#[derive(PartialEq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum SpiMode {
     #[default]
     Mode0,
@@ -108,6 +119,30 @@ pub enum SpiMode {
     Mode3,
 }
 
+impl SpiMode {
+    /// Converts the `SpiMode` variants into the corresponding spidev clock flags
+    fn to_flags(self) -> SpiModeFlags {
+        match self {
+            SpiMode::Mode0 => SpiModeFlags::SPI_MODE_0,
+            SpiMode::Mode1 => SpiModeFlags::SPI_MODE_1,
+            SpiMode::Mode2 => SpiModeFlags::SPI_MODE_2,
+            SpiMode::Mode3 => SpiModeFlags::SPI_MODE_3,
+        }
+    }
+
+    /// Extracts the clock polarity and phase from a spidev mode
+    fn from_flags(flags: SpiModeFlags) -> SpiMode {
+        let cpol = flags.contains(SpiModeFlags::SPI_CPOL);
+        let cpha = flags.contains(SpiModeFlags::SPI_CPHA);
+        match (cpol, cpha) {
+            (false, false) => SpiMode::Mode0,
+            (false, true) => SpiMode::Mode1,
+            (true, false) => SpiMode::Mode2,
+            (true, true) => SpiMode::Mode3,
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum ComMode {
     FullDuplex,
@@ -121,11 +156,345 @@ impl Default for ComMode {
     }
 }
 
-fn spi_open_error() -> Error {
-    Error::new(
-        ErrorKind::NotFound,
-        "Error: Unable to open the spi device. Did you set \"dtparam=spi=on\" in /boot/config.txt?",
-    )
+/**
+ * Decides what happens if the bytes received in full duplex mode do not fit into the internal buffer of [`SerialPi`].
+ *
+ * [`SerialPi`]: ./struct.SerialPi.html
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverflowPolicy {
+    /// The buffer grows above its capacity
+    #[default]
+    Grow,
+    /// The oldest buffered bytes are dropped to make room for the received ones
+    DropOldest,
+    /// `write` returns an error before anything is transferred
+    Error,
+}
+
+/**
+ * A data word that can be transferred with the word based functions of [`SerialPi`].
+ *
+ * The spidev driver stores words of up to 8 bits in one byte, words of up to 16 bits in two bytes and words of up to 32 bits in four bytes, always in the native byte order.
+ * Use `u8`, `u16` or `u32` matching the configured bits per word.
+ *
+ * [`SerialPi`]: ./struct.SerialPi.html
+ */
+pub trait Word: Copy + Default {
+    /// Number of bytes the driver uses to store one word
+    const SIZE: usize;
+    /// Writes the word in native byte order into `bytes`
+    fn write_bytes(self, bytes: &mut [u8]);
+    /// Reads a word in native byte order from `bytes`
+    fn read_bytes(bytes: &[u8]) -> Self;
+}
+
+impl Word for u8 {
+    const SIZE: usize = 1;
+    fn write_bytes(self, bytes: &mut [u8]) {
+        bytes[0] = self;
+    }
+    fn read_bytes(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+}
+
+impl Word for u16 {
+    const SIZE: usize = 2;
+    fn write_bytes(self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.to_ne_bytes());
+    }
+    fn read_bytes(bytes: &[u8]) -> Self {
+        u16::from_ne_bytes([bytes[0], bytes[1]])
+    }
+}
+
+impl Word for u32 {
+    const SIZE: usize = 4;
+    fn write_bytes(self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.to_ne_bytes());
+    }
+    fn read_bytes(bytes: &[u8]) -> Self {
+        u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+}
+
+/// Returns the number of bytes the spidev driver uses to store a word of the given size
+fn word_size(bits_per_word: u8) -> usize {
+    match bits_per_word {
+        0..=8 => 1,
+        9..=16 => 2,
+        _ => 4,
+    }
+}
+
+/// Reverses the bit order of every word in a buffer packed by the spidev rules.
+/// Only the lowest `bits_per_word` bits of each word are used.
+/// Fails if the buffer ends in the middle of a word.
+fn reverse_words(bytes: &mut [u8], bits_per_word: u8) -> Result<()> {
+    let shift = 32 - u32::from(bits_per_word);
+    let size = word_size(bits_per_word);
+    if !bytes.len().is_multiple_of(size) {
+        return Err(Error::InvalidArgument(
+            "buffer length is not a multiple of the word size",
+        ));
+    }
+    match size {
+        1 => {
+            for byte in bytes.iter_mut() {
+                *byte = (u32::from(*byte).reverse_bits() >> shift) as u8;
+            }
+        }
+        2 => {
+            for chunk in bytes.chunks_exact_mut(2) {
+                let word = u32::from(u16::read_bytes(chunk)).reverse_bits() >> shift;
+                (word as u16).write_bytes(chunk);
+            }
+        }
+        _ => {
+            for chunk in bytes.chunks_exact_mut(4) {
+                let word = u32::read_bytes(chunk).reverse_bits() >> shift;
+                word.write_bytes(chunk);
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ * A gpio that is used as chip select line instead of the CE pins of the spi controller.
+ *
+ * The line is asserted before every transaction and released afterwards. The setup delay is waited after asserting the line and the hold delay before releasing it.
+ */
+pub struct ChipSelect {
+    gpio: GPIO,
+    active_high: bool,
+    setup_delay: Duration,
+    hold_delay: Duration,
+}
+
+impl ChipSelect {
+    /**
+     * Switches the gpio into write mode and releases the line.
+     *
+     * # Errors
+     * Returns an error if the gpio can't be configured.
+     */
+    pub fn new(
+        mut gpio: GPIO,
+        active_high: bool,
+        setup_delay: Duration,
+        hold_delay: Duration,
+    ) -> Result<ChipSelect> {
+        if gpio.current_mode() != GPIOMode::Write {
+            gpio.set_mode(GPIOMode::Write)?;
+        }
+        let chip_select = ChipSelect {
+            gpio,
+            active_high,
+            setup_delay,
+            hold_delay,
+        };
+        chip_select.gpio.set(chip_select.level(false))?;
+        Ok(chip_select)
+    }
+
+    /**
+     * Returns the gpio number of the chip select line.
+     */
+    pub fn gpio_number(&self) -> u8 {
+        self.gpio.gpio_number()
+    }
+
+    /// Drives the line to its active level and waits the setup delay
+    pub(crate) fn assert(&self) -> Result<()> {
+        self.gpio.set(self.level(true))?;
+        thread::sleep(self.setup_delay);
+        Ok(())
+    }
+
+    /// Waits the hold delay and drives the line to its inactive level
+    pub(crate) fn release(&self) -> Result<()> {
+        thread::sleep(self.hold_delay);
+        self.gpio.set(self.level(false))
+    }
+
+    fn level(&self, active: bool) -> GPIOData {
+        if active == self.active_high {
+            GPIOData::High
+        } else {
+            GPIOData::Low
+        }
+    }
+}
+
+/**
+ * Decides how [`SerialPi`] handles transfers that are bigger than the buffer of the spidev driver (see `SerialPi::max_transfer_size`).
+ *
+ * [`SerialPi`]: ./struct.SerialPi.html
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Chunking {
+    /// The transfer is split into chunks and the chip select is released between them
+    #[default]
+    Split,
+    /// The transfer is split into chunks and the chip select stays asserted between them
+    KeepSelected,
+    /// The transfer fails with an error
+    Atomic,
+}
+
+/// Reads the transfer limit of the spidev driver
+fn spidev_bufsiz() -> usize {
+    fs::read_to_string(SPIDEV_BUFSIZ_PATH)
+        .ok()
+        .and_then(|bufsiz| bufsiz.trim().parse().ok())
+        .unwrap_or(DEFAULT_SPIDEV_BUFSIZ)
+}
+
+/**
+ * A single transfer on the spi bus.
+ */
+pub enum Transfer<'a> {
+    /// Sends all bytes and ignores the received ones
+    Write(&'a [u8]),
+    /// Fills the buffer with the received bytes. The transmitted data is undefined.
+    Read(&'a mut [u8]),
+    /// Sends the first buffer and stores the received bytes in the second one. Both have the same length.
+    ReadWrite(&'a [u8], &'a mut [u8]),
+    /// Sends the buffer and overwrites it with the received bytes
+    InPlace(&'a mut [u8]),
+}
+
+/**
+ * A spi controller that can execute transfers.
+ *
+ * It is implemented by [`SpiDevice`] for the spidev driver. [`SerialPi`] builds all of its functions on this trait, so another implementation can be used to mock the bus.
+ *
+ * The mode is passed as the bits of the spidev interface, e.g. `0x01` for the clock phase, `0x02` for the clock polarity and `0x04` for an active high chip select.
+ *
+ * [`SpiDevice`]: ./struct.SpiDevice.html
+ * [`SerialPi`]: ./struct.SerialPi.html
+ */
+pub trait SpiTransport {
+    /// Executes one transfer. The hardware chip select stays asserted afterwards if `keep_selected` is true.
+    fn transfer(&mut self, transfer: Transfer, keep_selected: bool) -> Result<()>;
+    /// Returns the biggest transfer that is accepted
+    fn max_transfer_size(&self) -> usize;
+    fn set_speed_hz(&mut self, speed_hz: u32) -> Result<()>;
+    fn speed_hz(&self) -> Result<u32>;
+    fn set_mode(&mut self, mode: u32) -> Result<()>;
+    fn mode(&self) -> Result<u32>;
+    /// Fails with an `Error::Io` of `EINVAL` if the controller doesn't support the bit order. [`SerialPi`] reverses the bits in software then.
+    ///
+    /// [`SerialPi`]: ./struct.SerialPi.html
+    fn set_lsb_first(&mut self, lsb_first: bool) -> Result<()>;
+    fn lsb_first(&self) -> Result<bool>;
+    fn set_bits_per_word(&mut self, bits_per_word: u8) -> Result<()>;
+    fn bits_per_word(&self) -> Result<u8>;
+}
+
+/**
+ * A chip enable line of the spidev driver, e.g. /dev/spidev0.0.
+ */
+pub struct SpiDevice {
+    spidev: Spidev,
+    max_transfer_size: usize,
+}
+
+impl SpiDevice {
+    /**
+     * Opens the spidev device of the chip enable line.
+     *
+     * # Errors
+     * Returns `Error::SpiNotEnabled` if the spi device can't be opened. It might be already in use or the raspberry is not configured correctly. Check the [documentation](https://www.raspberrypi.org/documentation/hardware/raspberrypi/spi/README.md#overview) of the raspberry pi in this case.
+     */
+    pub fn open(device: Device) -> Result<SpiDevice> {
+        //TODO: Check that correponding GPIOS are free
+        let path = match device {
+            Device::CE0 => SPI_PATH0,
+            Device::CE1 => SPI_PATH1,
+        };
+        match Spidev::open(path) {
+            Ok(spidev) => Ok(SpiDevice {
+                spidev,
+                max_transfer_size: spidev_bufsiz(),
+            }),
+            Err(source) => Err(Error::SpiNotEnabled { source }),
+        }
+    }
+}
+
+impl SpiTransport for SpiDevice {
+    fn transfer(&mut self, transfer: Transfer, keep_selected: bool) -> Result<()> {
+        let mut transfer = match transfer {
+            Transfer::Write(tx) => SpidevTransfer::write(tx),
+            Transfer::Read(rx) => SpidevTransfer::read(rx),
+            Transfer::ReadWrite(tx, rx) => SpidevTransfer::read_write(tx, rx),
+            Transfer::InPlace(buf) => SpidevTransfer::read_write_in_place(buf),
+        };
+        // asks the driver to keep the chip select asserted after the transfer
+        transfer.cs_change = u8::from(keep_selected);
+        Ok(self.spidev.transfer(&mut transfer)?)
+    }
+
+    /// Read from /sys/module/spidev/parameters/bufsiz when the device is opened
+    fn max_transfer_size(&self) -> usize {
+        self.max_transfer_size
+    }
+
+    fn set_speed_hz(&mut self, speed_hz: u32) -> Result<()> {
+        Ok(self
+            .spidev
+            .configure(&SpidevOptions::new().max_speed_hz(speed_hz).build())?)
+    }
+
+    fn speed_hz(&self) -> Result<u32> {
+        Ok(spidevioctl::get_max_speed_hz(self.spidev.as_raw_fd())?)
+    }
+
+    fn set_mode(&mut self, mode: u32) -> Result<()> {
+        let flags = SpiModeFlags::from_bits_truncate(mode);
+        Ok(spidevioctl::set_mode(self.spidev.as_raw_fd(), flags)?)
+    }
+
+    fn mode(&self) -> Result<u32> {
+        Ok(u32::from(spidevioctl::get_mode(self.spidev.as_raw_fd())?))
+    }
+
+    fn set_lsb_first(&mut self, lsb_first: bool) -> Result<()> {
+        Ok(self
+            .spidev
+            .configure(&SpidevOptions::new().lsb_first(lsb_first).build())?)
+    }
+
+    fn lsb_first(&self) -> Result<bool> {
+        Ok(spidevioctl::get_lsb_first(self.spidev.as_raw_fd())? != 0)
+    }
+
+    fn set_bits_per_word(&mut self, bits_per_word: u8) -> Result<()> {
+        Ok(self
+            .spidev
+            .configure(&SpidevOptions::new().bits_per_word(bits_per_word).build())?)
+    }
+
+    fn bits_per_word(&self) -> Result<u8> {
+        Ok(spidevioctl::get_bits_per_word(self.spidev.as_raw_fd())?)
+    }
+}
+
+/// Reads from a transport with single read transfers
+struct TransportReader<'a, T: SpiTransport>(&'a mut T);
+
+impl<'a, T: SpiTransport> Read for TransportReader<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len();
+        self.0.transfer(Transfer::Read(buf), false)?;
+        Ok(len)
+    }
 }
 
 /**
@@ -143,16 +512,35 @@ fn spi_open_error() -> Error {
  *
  * In full duplex mode, the data that was received with `write` will be buffered internally. Calls to the `Read` and `BufRead` trait implementation will first read out the buffered content and will issue an actual spi read if the buffer is exhausted.
  * The buffer will be reallocated if the last read bytes do not fit. It will preserve the capacity after that until a resize is issued with `try_shrink_to`.
+ * To bound the buffer to its capacity set an [`OverflowPolicy`] with `set_overflow_policy`.
+ *
+ * Use `transfer` or `transfer_in_place` to receive the bytes directly into a buffer of your own. These functions do not touch the internal buffer.
  *
  * In half duplex mode the buffer will not be filled when calling write, but it will be consumed when calling read.
+ *
+ * Instead of the CE pins any gpio can be used as chip select line with `set_chip_select`.
+ *
+ * The spidev driver limits the size of a single transfer (4096 bytes by default). Bigger transfers are split into chunks as configured with [`Chunking`].
+ *
+ * Any [`SpiTransport`] can be used instead of the spidev driver, e.g. to test a driver against a mocked bus.
+ *
+ * [`OverflowPolicy`]: ./enum.OverflowPolicy.html
+ * [`Chunking`]: ./enum.Chunking.html
+ * [`SpiTransport`]: ./trait.SpiTransport.html
  */
-pub struct SerialPi {
-    device: Spidev,
+pub struct SerialPi<T: SpiTransport = SpiDevice> {
+    device: T,
     pub com_mode: ComMode,
-    read_buffer: Vec<u8>,
+    read_buffer: ReadBuffer,
+    bits_per_word: u8,
+    software_lsb_first: bool,
+    chip_select: Option<ChipSelect>,
+    chip_selected: bool,
+    max_transfer_size: usize,
+    chunking: Chunking,
 }
 
-impl SerialPi {
+impl SerialPi<SpiDevice> {
     /**
      * Calls `with_capacity` with a buffer size of 1000 bytes.
      */
@@ -161,7 +549,7 @@ impl SerialPi {
         speed: Speed,
         spi_mode: SpiMode,
         communication_mode: ComMode,
-    ) -> io::Result<SerialPi> {
+    ) -> Result<SerialPi<SpiDevice>> {
         SerialPi::with_capacity(device, speed, spi_mode, communication_mode, 1000)
     }
 
@@ -171,7 +559,7 @@ impl SerialPi {
      * Note: The SpiMode and the ComMode have a default value.
      *
      * # Errors
-     * Can return an error if the spi device can't be opened. It might be already in use or the raspberry is not configured correctly. Check the [documentation](https://www.raspberrypi.org/documentation/hardware/raspberrypi/spi/README.md#overview) of the raspberry pi in this case.
+     * Returns `Error::SpiNotEnabled` if the spi device can't be opened. It might be already in use or the raspberry is not configured correctly. Check the [documentation](https://www.raspberrypi.org/documentation/hardware/raspberrypi/spi/README.md#overview) of the raspberry pi in this case.
      */
     pub fn with_capacity(
         device: Device,
@@ -179,34 +567,42 @@ impl SerialPi {
         spi_mode: SpiMode,
         communication_mode: ComMode,
         buffer_capacity: usize,
-    ) -> io::Result<SerialPi> {
-        //TODO: Check that correponding GPIOS are free
-        let mut spi = match device {
-            Device::CE0 => match Spidev::open(SPI_PATH0) {
-                Err(_) => return Err(spi_open_error()),
-                Ok(device) => device,
-            },
-            Device::CE1 => match Spidev::open(SPI_PATH1) {
-                Err(_) => return Err(spi_open_error()),
-                Ok(device) => device,
-            },
-        };
-        let options = SpidevOptions::new()
-            .bits_per_word(8)
-            .max_speed_hz(speed.to_int())
-            .mode(match spi_mode {
-                SpiMode::Mode0 => SpiModeFlags::SPI_MODE_0,
-                SpiMode::Mode1 => SpiModeFlags::SPI_MODE_1,
-                SpiMode::Mode2 => SpiModeFlags::SPI_MODE_2,
-                SpiMode::Mode3 => SpiModeFlags::SPI_MODE_3,
-            })
-            .lsb_first(false)
-            .build();
-        spi.configure(&options)?;
+    ) -> Result<SerialPi<SpiDevice>> {
+        SerialPi::with_transport(
+            SpiDevice::open(device)?,
+            speed,
+            spi_mode,
+            communication_mode,
+            buffer_capacity,
+        )
+    }
+}
+
+impl<T: SpiTransport> SerialPi<T> {
+    /**
+     * Creates a serial wrapper on the given transport and configures it like `with_capacity`.
+     */
+    pub fn with_transport(
+        mut transport: T,
+        speed: Speed,
+        spi_mode: SpiMode,
+        communication_mode: ComMode,
+        buffer_capacity: usize,
+    ) -> Result<SerialPi<T>> {
+        transport.set_bits_per_word(8)?;
+        transport.set_speed_hz(speed.to_int())?;
+        transport.set_lsb_first(false)?;
+        transport.set_mode(spi_mode.to_flags().bits())?;
         Ok(SerialPi {
-            device: spi,
+            max_transfer_size: transport.max_transfer_size(),
+            device: transport,
             com_mode: communication_mode,
-            read_buffer: Vec::with_capacity(buffer_capacity),
+            read_buffer: ReadBuffer::with_capacity(buffer_capacity),
+            bits_per_word: 8,
+            software_lsb_first: false,
+            chip_select: None,
+            chip_selected: false,
+            chunking: Chunking::default(),
         })
     }
 
@@ -221,33 +617,451 @@ impl SerialPi {
      * Shrinks the internal buffer to fit its length. If the new capacity is les then the desired, the capacity is extended to match the desired.
      * Already read bytes are not dropped. To do that call `consume`.
      * The actual capacity might be greater than the desired.
+     *
+     * The desired capacity is used as the limit for the [`OverflowPolicy`].
+     *
+     * [`OverflowPolicy`]: ./enum.OverflowPolicy.html
      */
     pub fn try_shrink_to(&mut self, desired_capacity: usize) -> usize {
-        self.read_buffer.shrink_to_fit();
-        if self.read_buffer.capacity() < desired_capacity {
-            let reserve = desired_capacity - self.read_buffer.len();
-            self.read_buffer.reserve_exact(reserve);
+        self.read_buffer.shrink_to(desired_capacity)
+    }
+
+    /**
+     * Sets what happens if the received bytes do not fit into the internal buffer. The limit is the capacity that was requested on creation or with `try_shrink_to`.
+     *
+     * Already buffered bytes above the limit are kept until they are read or consumed.
+     */
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.read_buffer.set_overflow_policy(overflow_policy);
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.read_buffer.overflow_policy()
+    }
+
+    /**
+     * Does a full duplex transfer of `tx` and stores the received bytes in `rx`. The internal buffer is bypassed, regardless of the [`ComMode`].
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if `tx` and `rx` have different lengths.
+     *
+     * [`ComMode`]: ./enum.ComMode.html
+     */
+    pub fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        if tx.len() != rx.len() {
+            return Err(Error::InvalidArgument(
+                "transmit and receive buffer have different lengths",
+            ));
         }
-        self.read_buffer.capacity()
+        let mut reversed = Vec::new();
+        let tx = if self.software_lsb_first {
+            reversed.extend_from_slice(tx);
+            self.reverse_if_lsb_first(&mut reversed)?;
+            reversed.as_slice()
+        } else {
+            tx
+        };
+        self.chunked(tx.len(), |device, range, keep| {
+            device.transfer(
+                Transfer::ReadWrite(&tx[range.clone()], &mut rx[range]),
+                keep,
+            )
+        })?;
+        self.reverse_if_lsb_first(rx)
+    }
+
+    /**
+     * Does a full duplex transfer of `buf` and overwrites it with the received bytes. The internal buffer is bypassed, regardless of the [`ComMode`].
+     *
+     * [`ComMode`]: ./enum.ComMode.html
+     */
+    pub fn transfer_in_place(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reverse_if_lsb_first(buf)?;
+        self.chunked(buf.len(), |device, range, keep| {
+            device.transfer(Transfer::InPlace(&mut buf[range]), keep)
+        })?;
+        self.reverse_if_lsb_first(buf)
+    }
+
+    /**
+     * Changes the bus clock of the open device.
+     */
+    pub fn set_speed(&mut self, speed: Speed) -> Result<()> {
+        self.device.set_speed_hz(speed.to_int())
+    }
+
+    /**
+     * Reads the maximum bus clock in Hz back from the driver.
+     */
+    pub fn speed_hz(&self) -> Result<u32> {
+        self.device.speed_hz()
+    }
+
+    /**
+     * Changes clock polarity and clock phase of the open device. All other mode flags are preserved.
+     */
+    pub fn set_spi_mode(&mut self, spi_mode: SpiMode) -> Result<()> {
+        let flags = (self.mode_flags()? - SpiModeFlags::SPI_MODE_3) | spi_mode.to_flags();
+        self.device.set_mode(flags.bits())
+    }
+
+    /**
+     * Reads the clock polarity and clock phase back from the driver.
+     */
+    pub fn spi_mode(&self) -> Result<SpiMode> {
+        Ok(SpiMode::from_flags(self.mode_flags()?))
+    }
+
+    /**
+     * Transmits the least significant bit of each word first if set to true.
+     *
+     * Many controllers (including the one of the raspberry pi) do not support this bit order. In this case the bits of every word are reversed in software before they are send and after they are received.
+     * Buffers have to hold whole words then, otherwise the transfer fails with `Error::InvalidArgument`.
+     */
+    pub fn set_lsb_first(&mut self, lsb_first: bool) -> Result<()> {
+        match self.device.set_lsb_first(lsb_first) {
+            Ok(()) => self.software_lsb_first = false,
+            Err(Error::Io(ref why)) if lsb_first && why.raw_os_error() == Some(libc::EINVAL) => {
+                self.software_lsb_first = true
+            }
+            Err(why) => return Err(why),
+        }
+        Ok(())
+    }
+
+    /**
+     * Returns true if the least significant bit is transmitted first, either by the driver or by the software fallback.
+     */
+    pub fn lsb_first(&self) -> Result<bool> {
+        Ok(self.software_lsb_first || self.device.lsb_first()?)
+    }
+
+    /**
+     * Changes the word size of the open device. Valid sizes range from 1 to 32 bits, but the controller might support only some of them.
+     *
+     * Words with more than 8 bits should be transferred with the word based functions like `transfer_words`.
+     */
+    pub fn set_bits_per_word(&mut self, bits_per_word: u8) -> Result<()> {
+        if bits_per_word == 0 || bits_per_word > 32 {
+            return Err(Error::InvalidArgument(
+                "bits per word have to be between 1 and 32",
+            ));
+        }
+        self.device.set_bits_per_word(bits_per_word)?;
+        self.bits_per_word = bits_per_word;
+        Ok(())
+    }
+
+    /**
+     * Reads the word size back from the driver.
+     */
+    pub fn bits_per_word(&self) -> Result<u8> {
+        self.device.bits_per_word()
+    }
+
+    /**
+     * Makes the chip select line active high instead of active low.
+     */
+    pub fn set_cs_high(&mut self, cs_high: bool) -> Result<()> {
+        self.set_mode_flag(SpiModeFlags::SPI_CS_HIGH, cs_high)
+    }
+
+    /**
+     * Returns true if the chip select line is active high.
+     */
+    pub fn cs_high(&self) -> Result<bool> {
+        Ok(self.mode_flags()?.contains(SpiModeFlags::SPI_CS_HIGH))
+    }
+
+    /**
+     * Shares MOSI as a single bidirectional data line (SI/SO signals).
+     */
+    pub fn set_three_wire(&mut self, three_wire: bool) -> Result<()> {
+        self.set_mode_flag(SpiModeFlags::SPI_3WIRE, three_wire)
+    }
+
+    /**
+     * Returns true if the device is configured for three wire communication.
+     */
+    pub fn three_wire(&self) -> Result<bool> {
+        Ok(self.mode_flags()?.contains(SpiModeFlags::SPI_3WIRE))
+    }
+
+    /**
+     * Connects MOSI to MISO inside the controller. Useful to test the communication without a slave.
+     */
+    pub fn set_loopback(&mut self, loopback: bool) -> Result<()> {
+        self.set_mode_flag(SpiModeFlags::SPI_LOOP, loopback)
+    }
+
+    /**
+     * Returns true if the controller loops MOSI back to MISO.
+     */
+    pub fn loopback(&self) -> Result<bool> {
+        Ok(self.mode_flags()?.contains(SpiModeFlags::SPI_LOOP))
+    }
+
+    /**
+     * Stops the controller from toggling the chip select line during transfers.
+     */
+    pub fn set_no_cs(&mut self, no_cs: bool) -> Result<()> {
+        self.set_mode_flag(SpiModeFlags::SPI_NO_CS, no_cs)
+    }
+
+    /**
+     * Returns true if the chip select line is not used by the controller.
+     */
+    pub fn no_cs(&self) -> Result<bool> {
+        Ok(self.mode_flags()?.contains(SpiModeFlags::SPI_NO_CS))
+    }
+
+    /**
+     * Returns the biggest transfer the spidev driver accepts. It is read from /sys/module/spidev/parameters/bufsiz when the device is opened.
+     */
+    pub fn max_transfer_size(&self) -> usize {
+        self.max_transfer_size
+    }
+
+    /**
+     * Sets how transfers bigger than `max_transfer_size` are handled.
+     *
+     * Note: [`Chunking::KeepSelected`] only asks the driver to keep the hardware chip select asserted, some controllers might release it anyway. A gpio chip select (see `set_chip_select`) is always kept asserted.
+     *
+     * [`Chunking::KeepSelected`]: ./enum.Chunking.html#variant.KeepSelected
+     */
+    pub fn set_chunking(&mut self, chunking: Chunking) {
+        self.chunking = chunking;
+    }
+
+    pub fn chunking(&self) -> Chunking {
+        self.chunking
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.device
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.device
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    /**
+     * Uses a gpio as chip select line. The controller will stop toggling its own chip select line (see `set_no_cs`).
+     * Passing `None` restores the hardware chip select. The previously used [`ChipSelect`] is returned.
+     *
+     * [`ChipSelect`]: ./struct.ChipSelect.html
+     */
+    pub fn set_chip_select(
+        &mut self,
+        chip_select: Option<ChipSelect>,
+    ) -> Result<Option<ChipSelect>> {
+        self.set_no_cs(chip_select.is_some())?;
+        Ok(std::mem::replace(&mut self.chip_select, chip_select))
+    }
+
+    /**
+     * Asserts the gpio chip select line (if one is set), calls `f` and releases the line afterwards.
+     * All transfers issued inside `f` keep the line asserted.
+     *
+     * Without a gpio chip select `f` is just called.
+     */
+    pub fn transaction<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut SerialPi<T>) -> Result<R>,
+    {
+        if self.chip_selected || self.chip_select.is_none() {
+            return f(self);
+        }
+        if let Some(ref chip_select) = self.chip_select {
+            chip_select.assert()?;
+        }
+        self.chip_selected = true;
+        let result = f(self);
+        self.chip_selected = false;
+        let released = match self.chip_select {
+            Some(ref chip_select) => chip_select.release(),
+            None => Ok(()),
+        };
+        let value = result?;
+        released?;
+        Ok(value)
+    }
+
+    /**
+     * Sends the given words and ignores the received data.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if the size of `W` does not match the configured bits per word.
+     */
+    pub fn write_words<W: Word>(&mut self, words: &[W]) -> Result<()> {
+        let tx = self.pack_words(words)?;
+        self.chunked(tx.len(), |device, range, keep| {
+            device.transfer(Transfer::Write(&tx[range]), keep)
+        })
+    }
+
+    /**
+     * Reads words from the spi device. The transmitted data is undefined.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if the size of `W` does not match the configured bits per word.
+     */
+    pub fn read_words<W: Word>(&mut self, words: &mut [W]) -> Result<()> {
+        self.check_word_size::<W>()?;
+        let mut rx = vec![0_u8; words.len() * W::SIZE];
+        self.chunked(rx.len(), |device, range, keep| {
+            device.transfer(Transfer::Read(&mut rx[range]), keep)
+        })?;
+        self.unpack_words(&mut rx, words)
+    }
+
+    /**
+     * Does a full duplex transfer of words. The received words are stored in `rx`, the internal buffer is not touched.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if `tx` and `rx` have different lengths or the size of `W` does not match the configured bits per word.
+     */
+    pub fn transfer_words<W: Word>(&mut self, tx: &[W], rx: &mut [W]) -> Result<()> {
+        if tx.len() != rx.len() {
+            return Err(Error::InvalidArgument(
+                "transmit and receive buffer have different lengths",
+            ));
+        }
+        let tx = self.pack_words(tx)?;
+        let mut received = vec![0_u8; tx.len()];
+        self.chunked(tx.len(), |device, range, keep| {
+            device.transfer(
+                Transfer::ReadWrite(&tx[range.clone()], &mut received[range]),
+                keep,
+            )
+        })?;
+        self.unpack_words(&mut received, rx)
+    }
+
+    /// Returns an error if a word of type `W` is not how the driver stores the configured word size
+    fn check_word_size<W: Word>(&self) -> Result<()> {
+        if W::SIZE != word_size(self.bits_per_word) {
+            return Err(Error::InvalidArgument(
+                "word type does not match the configured bits per word",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Packs words into the byte layout of the driver and reverses them if necessary
+    fn pack_words<W: Word>(&self, words: &[W]) -> Result<Vec<u8>> {
+        self.check_word_size::<W>()?;
+        let mut bytes = vec![0_u8; words.len() * W::SIZE];
+        for (word, chunk) in words.iter().zip(bytes.chunks_exact_mut(W::SIZE)) {
+            word.write_bytes(chunk);
+        }
+        self.reverse_if_lsb_first(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Unpacks received bytes into words and reverses them if necessary
+    fn unpack_words<W: Word>(&self, bytes: &mut [u8], words: &mut [W]) -> Result<()> {
+        self.reverse_if_lsb_first(bytes)?;
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(W::SIZE)) {
+            *word = W::read_bytes(chunk);
+        }
+        Ok(())
+    }
+
+    /// Applies the software fallback for the lsb first bit order
+    fn reverse_if_lsb_first(&self, bytes: &mut [u8]) -> Result<()> {
+        if self.software_lsb_first {
+            reverse_words(bytes, self.bits_per_word)?;
+        }
+        Ok(())
+    }
+
+    /// Splits a transfer of `len` bytes at the driver limit according to the [`Chunking`] and calls `f` for every chunk.
+    /// `f` gets the range of the chunk and whether the hardware chip select should be kept asserted after it.
+    fn chunked<F>(&mut self, len: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut T, Range<usize>, bool) -> Result<()>,
+    {
+        if len <= self.max_transfer_size {
+            return self.transaction(|serial| f(&mut serial.device, 0..len, false));
+        }
+        // never split a word
+        let word_size = word_size(self.bits_per_word);
+        let chunk_size =
+            (self.max_transfer_size - self.max_transfer_size % word_size).max(word_size);
+        let chunks = (0..len)
+            .step_by(chunk_size)
+            .map(|start| start..(start + chunk_size).min(len));
+        match self.chunking {
+            Chunking::Atomic => Err(Error::TransferTooLarge {
+                len,
+                max: self.max_transfer_size,
+            }),
+            Chunking::Split => {
+                for chunk in chunks {
+                    self.transaction(|serial| f(&mut serial.device, chunk, false))?;
+                }
+                Ok(())
+            }
+            Chunking::KeepSelected => self.transaction(|serial| {
+                for chunk in chunks {
+                    let last = chunk.end == len;
+                    f(&mut serial.device, chunk, !last)?;
+                }
+                Ok(())
+            }),
+        }
+    }
+
+    /// Appends as many whole words as fit into the internal buffer and the limit of the driver.
+    /// The buffer is left unchanged if the device can't be read.
+    fn fill_read_buffer(&mut self) -> Result<()> {
+        let received = self.read_buffer.fill_from(
+            &mut TransportReader(&mut self.device),
+            self.max_transfer_size,
+            word_size(self.bits_per_word),
+        )?;
+        if self.software_lsb_first {
+            reverse_words(received, self.bits_per_word)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the current mode flags from the driver
+    fn mode_flags(&self) -> Result<SpiModeFlags> {
+        Ok(SpiModeFlags::from_bits_truncate(self.device.mode()?))
+    }
+
+    /// Sets or clears a single mode flag without touching the others
+    fn set_mode_flag(&mut self, flag: SpiModeFlags, enabled: bool) -> Result<()> {
+        let mut flags = self.mode_flags()?;
+        flags.set(flag, enabled);
+        self.device.set_mode(flags.bits())
     }
 }
 
-impl Read for SerialPi {
+impl<T: SpiTransport> Read for SerialPi<T> {
     /**
      * Fills buf with the bytes from the internal buffer. If buf.len() is greater then the buffered byte count, the serial device is read until buf is filled.
      */
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut buffer_read_count = self.read_buffer.as_slice().read(buf)?;
-        self.read_buffer.drain(0..buffer_read_count);
+        let buffer_read_count = self.read_buffer.read(buf);
         if buffer_read_count < buf.len() {
             let (_, rest_buffer) = buf.split_at_mut(buffer_read_count);
-            buffer_read_count += self.device.read(rest_buffer)?;
+            self.chunked(rest_buffer.len(), |device, range, keep| {
+                device.transfer(Transfer::Read(&mut rest_buffer[range]), keep)
+            })?;
+            self.reverse_if_lsb_first(rest_buffer)?;
         }
-        Ok(buffer_read_count)
+        Ok(buf.len())
     }
 }
 
-impl BufRead for SerialPi {
+impl<T: SpiTransport> BufRead for SerialPi<T> {
     /**
      * Does nothing if [`ComMode`] is not [`ComMode::FullDuplex`] and returns the internal buffer as slice.
      *
@@ -258,20 +1072,7 @@ impl BufRead for SerialPi {
      */
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
         if self.com_mode == ComMode::FullDuplex {
-            let buffer_length = self.read_buffer.len();
-            let bytes_read = {
-                let rest_buffer = {
-                    let capacity = self.read_buffer.capacity();
-                    unsafe {
-                        self.read_buffer.set_len(capacity);
-                    }
-                    let (_, rest_buffer) =
-                        self.read_buffer.as_mut_slice().split_at_mut(buffer_length);
-                    rest_buffer
-                };
-                self.device.read(rest_buffer)?
-            };
-            self.read_buffer.truncate(buffer_length + bytes_read);
+            self.transaction(|serial| serial.fill_read_buffer())?;
         }
         Ok(self.read_buffer.as_slice())
     }
@@ -285,47 +1086,87 @@ impl BufRead for SerialPi {
      */
     fn consume(&mut self, amt: usize) {
         if self.com_mode == ComMode::FullDuplex {
-            self.read_buffer.drain(0..(amt));
+            self.read_buffer.consume(amt);
         }
     }
 }
 
-impl Write for SerialPi {
+impl<T: SpiTransport> Write for SerialPi<T> {
     /**
      * Calls write in the spi device if [`ComMode`] is [`ComMode::HalfDuplex`].
      *
      * Does a full duplex transfer if [`ComMode`] is not [`ComMode::HalfDuplex`] and buffers the received bytes internally.
-     * The buffer is allowed to grow above its capacity. Call `consume` to drop already received bytes or `read` them. To deallocate buffer space call `try_shrink_to` (after consuming already received data).
+     * With the default [`OverflowPolicy`] the buffer is allowed to grow above its capacity. Call `consume` to drop already received bytes or `read` them. To deallocate buffer space call `try_shrink_to` (after consuming already received data).
      *
      * [`ComMode`]: ./enum.ComMode.html
      * [`ComMode::HalfDuplex`]: ./enum.ComMode.html#variant.HalfDuplex
+     * [`OverflowPolicy`]: ./enum.OverflowPolicy.html
      */
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut reversed = Vec::new();
+        let buf = if self.software_lsb_first {
+            reversed.extend_from_slice(buf);
+            self.reverse_if_lsb_first(&mut reversed)?;
+            reversed.as_slice()
+        } else {
+            buf
+        };
         if self.com_mode == ComMode::HalfDuplex {
-            self.device.write(buf)
+            self.chunked(buf.len(), |device, range, keep| {
+                device.transfer(Transfer::Write(&buf[range]), keep)
+            })?;
+            Ok(buf.len())
         } else {
+            self.read_buffer.reserve(buf.len())?;
             let mut read_data: Vec<u8> = vec![0_u8; buf.len()];
-            {
-                let mut transfer = SpidevTransfer::read_write(buf, read_data.as_mut_slice());
-                self.device.transfer(&mut transfer)?;
-            }
-            self.read_buffer.append(&mut read_data);
+            self.chunked(buf.len(), |device, range, keep| {
+                device.transfer(
+                    Transfer::ReadWrite(&buf[range.clone()], &mut read_data[range]),
+                    keep,
+                )
+            })?;
+            self.reverse_if_lsb_first(&mut read_data)?;
+            self.read_buffer.extend(&read_data);
             Ok(buf.len())
         }
     }
     /**
-     * Does nothing if [`ComMode`] is not [`ComMode::HalfDuplex`].
-     *
-     * Flushes the spi device if [`ComMode`] is [`ComMode::HalfDuplex`].
-     *
-     * [`ComMode`]: ./enum.ComMode.html
-     * [`ComMode::HalfDuplex`]: ./enum.ComMode.html#variant.HalfDuplex
+     * Does nothing. Every write is transferred immediately.
      */
     fn flush(&mut self) -> io::Result<()> {
-        if self.com_mode == ComMode::HalfDuplex {
-            self.device.flush()
-        } else {
-            Ok(())
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_words_of_every_size() {
+        let mut bytes = [0b0000_0001, 0b0000_0110];
+        reverse_words(&mut bytes, 3).unwrap();
+        assert_eq!(bytes, [0b0000_0100, 0b0000_0011]);
+
+        let mut bytes = 0x0123_u16.to_ne_bytes();
+        reverse_words(&mut bytes, 12).unwrap();
+        assert_eq!(u16::from_ne_bytes(bytes), 0xC48);
+
+        let mut bytes = 0x0000_0001_u32.to_ne_bytes();
+        reverse_words(&mut bytes, 32).unwrap();
+        assert_eq!(u32::from_ne_bytes(bytes), 0x8000_0000);
+    }
+
+    #[test]
+    fn reverse_words_rejects_a_partial_word() {
+        for &(len, bits_per_word) in [(3, 16), (5, 9), (6, 24), (2, 32)].iter() {
+            let mut bytes = vec![0xFF; len];
+            match reverse_words(&mut bytes, bits_per_word) {
+                Err(Error::InvalidArgument(_)) => {}
+                other => panic!("{} bytes of {} bits: {:?}", len, bits_per_word, other),
+            }
+            // nothing was reversed
+            assert!(bytes.iter().all(|&byte| byte == 0xFF));
         }
     }
 }
@@ -0,0 +1,183 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! Hobby servos and ESCs driven by a pwm signal.
+//!
+
+use crate::error::{Error, Result};
+use crate::pwm::PwmOutput;
+use std::time::Duration;
+
+/**
+ * Maps the angle range of a servo to the pulse widths it expects.
+ *
+ * The default fits most hobby servos: pulses from 1ms to 2ms for 0° to 180° every 20ms. For an ESC the "angle" can be used as throttle, e.g. from 0 to 100.
+ *
+ * With the `serde` feature the calibration can be serialized.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServoCalibration {
+    /// Pulse width at the minimal angle
+    pub min_pulse: Duration,
+    /// Pulse width at the maximal angle
+    pub max_pulse: Duration,
+    pub min_angle: f64,
+    pub max_angle: f64,
+    /// Time between two pulses
+    pub period: Duration,
+}
+
+impl Default for ServoCalibration {
+    fn default() -> Self {
+        ServoCalibration {
+            min_pulse: Duration::from_micros(1000),
+            max_pulse: Duration::from_micros(2000),
+            min_angle: 0.0,
+            max_angle: 180.0,
+            period: Duration::from_millis(20),
+        }
+    }
+}
+
+impl ServoCalibration {
+    /// Returns an error if the pulses do not fit into the period or the ranges are empty
+    fn check(&self) -> Result<()> {
+        if self.min_pulse >= self.max_pulse
+            || self.max_pulse > self.period
+            || self.min_angle >= self.max_angle
+            || !self.min_angle.is_finite()
+            || !self.max_angle.is_finite()
+        {
+            return Err(Error::InvalidArgument("invalid servo calibration"));
+        }
+        Ok(())
+    }
+}
+
+/**
+ * A servo on any [`PwmOutput`], like the hardware [`PWM`] or the [`SoftPwm`].
+ *
+ * No pulses are sent until the first angle or pulse width is set.
+ *
+ * [`PwmOutput`]: ../pwm/trait.PwmOutput.html
+ * [`PWM`]: ../pwm/struct.PWM.html
+ * [`SoftPwm`]: ../soft_pwm/struct.SoftPwm.html
+ */
+pub struct Servo<P: PwmOutput> {
+    pwm: P,
+    calibration: ServoCalibration,
+    pulse_width: Option<Duration>,
+}
+
+impl<P: PwmOutput> Servo<P> {
+    /**
+     * Configures the period of the pwm and starts it without pulses.
+     *
+     * # Errors
+     * Returns an error if the calibration is invalid or the pwm can't be configured.
+     */
+    pub fn new(pwm: P, calibration: ServoCalibration) -> Result<Servo<P>> {
+        calibration.check()?;
+        pwm.set_duty_cycle(Duration::from_secs(0))?;
+        pwm.set_period(calibration.period)?;
+        pwm.enable()?;
+        Ok(Servo {
+            pwm,
+            calibration,
+            pulse_width: None,
+        })
+    }
+
+    /**
+     * Moves the servo to the angle.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if the angle is outside of the calibrated range.
+     */
+    pub fn set_angle(&mut self, angle: f64) -> Result<()> {
+        let calibration = self.calibration;
+        if !(calibration.min_angle..=calibration.max_angle).contains(&angle) {
+            return Err(Error::InvalidArgument(
+                "angle is outside of the calibrated range",
+            ));
+        }
+        let position =
+            (angle - calibration.min_angle) / (calibration.max_angle - calibration.min_angle);
+        let range = calibration.max_pulse - calibration.min_pulse;
+        self.set_pulse_width(calibration.min_pulse + range.mul_f64(position))
+    }
+
+    /**
+     * Returns the angle of the last set pulse width. Returns None if no pulses are sent.
+     */
+    pub fn angle(&self) -> Option<f64> {
+        let calibration = self.calibration;
+        self.pulse_width.map(|pulse_width| {
+            let position = (pulse_width - calibration.min_pulse).as_secs_f64()
+                / (calibration.max_pulse - calibration.min_pulse).as_secs_f64();
+            calibration.min_angle + position * (calibration.max_angle - calibration.min_angle)
+        })
+    }
+
+    /**
+     * Sends pulses of the given width.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if the width is outside of the calibrated range.
+     */
+    pub fn set_pulse_width(&mut self, pulse_width: Duration) -> Result<()> {
+        if pulse_width < self.calibration.min_pulse || pulse_width > self.calibration.max_pulse {
+            return Err(Error::InvalidArgument(
+                "pulse width is outside of the calibrated range",
+            ));
+        }
+        self.pwm.set_duty_cycle(pulse_width)?;
+        self.pulse_width = Some(pulse_width);
+        Ok(())
+    }
+
+    /**
+     * Returns the width of the sent pulses. Returns None if no pulses are sent.
+     */
+    pub fn pulse_width(&self) -> Option<Duration> {
+        self.pulse_width
+    }
+
+    /**
+     * Stops sending pulses. Most servos stop holding their position.
+     */
+    pub fn relax(&mut self) -> Result<()> {
+        self.pwm.set_duty_cycle(Duration::from_secs(0))?;
+        self.pulse_width = None;
+        Ok(())
+    }
+
+    pub fn calibration(&self) -> &ServoCalibration {
+        &self.calibration
+    }
+
+    /**
+     * Returns the pwm. It keeps sending the last pulses.
+     */
+    pub fn into_inner(self) -> P {
+        self.pwm
+    }
+}
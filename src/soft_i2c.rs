@@ -0,0 +1,289 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! An i2c master that toggles gpio pins in software.
+//!
+
+use crate::error::{Error, Result};
+use crate::gpio::{GPIOData, OpenDrainPin, GPIO};
+use crate::i2c::{Address, I2cTransport, Message, SMBUS_BLOCK_MAX};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/**
+ * A bit banged i2c master on two gpio pins.
+ *
+ * It can replace the hardware controller, e.g. for slaves that stretch the clock, which the controller of the BCM2835 does not handle correctly. Both lines need pull-up resistors. The pins are driven in open drain style: A LOW is driven, a HIGH is produced by releasing the line.
+ *
+ * Slaves may hold the clock LOW to stretch it. The master waits for the clock up to the stretch timeout.
+ *
+ * A missing acknowledge is reported like the kernel drivers do: As `ENXIO` for the address and as `EREMOTEIO` for a data byte.
+ *
+ * SoftI2c implements [`I2cTransport`], so an [`I2cPi`] can use it instead of the kernel interface:
+ * `I2cPi::with_transport(SoftI2c::new(scl, sda, 100_000)?, address)`.
+ *
+ * Any [`OpenDrainPin`] implementation can be used instead of [`GPIO`], e.g. to simulate a slave.
+ *
+ * [`I2cTransport`]: ../i2c/trait.I2cTransport.html
+ * [`I2cPi`]: ../i2c/struct.I2cPi.html
+ * [`OpenDrainPin`]: ../gpio/trait.OpenDrainPin.html
+ * [`GPIO`]: ../gpio/struct.GPIO.html
+ */
+pub struct SoftI2c<P: OpenDrainPin = GPIO> {
+    scl: P,
+    sda: P,
+    half_period: Duration,
+    stretch_timeout: Duration,
+    arbitration_lost: bool,
+}
+
+impl<P: OpenDrainPin> SoftI2c<P> {
+    /**
+     * Creates a bit banged i2c master with the given clock frequency in Hz and releases both lines.
+     * The stretch timeout is 25 ms.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if the frequency is 0 or an error of the pins.
+     */
+    pub fn new(scl: P, sda: P, frequency: u32) -> Result<SoftI2c<P>> {
+        let mut i2c = SoftI2c {
+            scl,
+            sda,
+            half_period: Duration::from_secs(0),
+            stretch_timeout: Duration::from_millis(25),
+            arbitration_lost: false,
+        };
+        i2c.set_frequency(frequency)?;
+        i2c.sda.release()?;
+        i2c.scl.release()?;
+        Ok(i2c)
+    }
+
+    /**
+     * Sets the clock frequency in Hz. The resulting clock is slower, because toggling the pins takes time as well.
+     */
+    pub fn set_frequency(&mut self, frequency: u32) -> Result<()> {
+        if frequency == 0 {
+            return Err(Error::InvalidArgument(
+                "i2c frequency has to be greater than 0",
+            ));
+        }
+        self.half_period = Duration::from_nanos(500_000_000 / u64::from(frequency));
+        Ok(())
+    }
+
+    pub fn frequency(&self) -> u32 {
+        (500_000_000 / self.half_period.as_nanos().max(1)) as u32
+    }
+
+    /**
+     * Sets how long a slave may hold the clock LOW.
+     */
+    pub fn set_stretch_timeout(&mut self, stretch_timeout: Duration) {
+        self.stretch_timeout = stretch_timeout;
+    }
+
+    pub fn stretch_timeout(&self) -> Duration {
+        self.stretch_timeout
+    }
+
+    pub fn into_inner(self) -> (P, P) {
+        (self.scl, self.sda)
+    }
+
+    fn wait(&self) {
+        if self.half_period > Duration::from_secs(0) {
+            thread::sleep(self.half_period);
+        }
+    }
+
+    /// Releases the clock and waits until no slave stretches it anymore
+    fn release_clock(&mut self) -> Result<()> {
+        self.scl.release()?;
+        let start = Instant::now();
+        while self.scl.level()? == GPIOData::Low {
+            if start.elapsed() > self.stretch_timeout {
+                return Err(Error::Timeout("i2c clock stretching timed out"));
+            }
+            thread::yield_now();
+        }
+        Ok(())
+    }
+
+    /// Leaves the bus to the other master
+    fn lose_arbitration(&mut self) -> Error {
+        self.arbitration_lost = true;
+        let _ = self.sda.release();
+        let _ = self.scl.release();
+        Error::Protocol("i2c arbitration lost")
+    }
+
+    /// A start condition if the bus is idle, a repeated start otherwise
+    fn start(&mut self, repeated: bool) -> Result<()> {
+        if repeated {
+            self.sda.release()?;
+            self.wait();
+            self.release_clock()?;
+            self.wait();
+        }
+        if self.sda.level()? == GPIOData::Low {
+            return Err(self.lose_arbitration());
+        }
+        self.sda.drive_low()?;
+        self.wait();
+        self.scl.drive_low()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.sda.drive_low()?;
+        self.wait();
+        self.release_clock()?;
+        self.wait();
+        self.sda.release()?;
+        self.wait();
+        Ok(())
+    }
+
+    fn write_bit(&mut self, bit: bool) -> Result<()> {
+        if bit {
+            self.sda.release()?;
+        } else {
+            self.sda.drive_low()?;
+        }
+        self.wait();
+        self.release_clock()?;
+        // another master drives LOW while this one sends HIGH
+        if bit && self.sda.level()? == GPIOData::Low {
+            return Err(self.lose_arbitration());
+        }
+        self.wait();
+        self.scl.drive_low()
+    }
+
+    fn read_bit(&mut self) -> Result<bool> {
+        self.sda.release()?;
+        self.wait();
+        self.release_clock()?;
+        let bit = self.sda.level()? == GPIOData::High;
+        self.wait();
+        self.scl.drive_low()?;
+        Ok(bit)
+    }
+
+    /// Sends a byte msb first and returns true if the slave acknowledged it
+    fn write_byte(&mut self, byte: u8) -> Result<bool> {
+        for bit in (0..8).rev() {
+            self.write_bit(byte & (1 << bit) != 0)?;
+        }
+        Ok(!self.read_bit()?)
+    }
+
+    fn read_byte(&mut self, acknowledge: bool) -> Result<u8> {
+        let mut byte = 0_u8;
+        for _ in 0..8 {
+            byte = (byte << 1) | self.read_bit()? as u8;
+        }
+        self.write_bit(!acknowledge)?;
+        Ok(byte)
+    }
+
+    fn write_address(&mut self, address: Address, read: bool, repeated: bool) -> Result<()> {
+        self.start(repeated)?;
+        let acknowledged = match address {
+            Address::SevenBit(address) => self.write_byte(address << 1 | read as u8)?,
+            Address::TenBit(address) => {
+                let high = 0b1111_0000 | ((address >> 7) as u8 & 0b110);
+                let mut acknowledged = self.write_byte(high)? && self.write_byte(address as u8)?;
+                // a read addresses the slave with a repeated start and the first byte only
+                if acknowledged && read {
+                    self.start(true)?;
+                    acknowledged = self.write_byte(high | 1)?;
+                }
+                acknowledged
+            }
+        };
+        if !acknowledged {
+            return Err(Error::Io(io::Error::from_raw_os_error(libc::ENXIO)));
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<()> {
+        let last = buf.len().saturating_sub(1);
+        for (index, byte) in buf.iter_mut().enumerate() {
+            *byte = self.read_byte(index != last)?;
+        }
+        Ok(())
+    }
+
+    fn transfer_messages(&mut self, address: Address, messages: &mut [Message]) -> Result<()> {
+        for (index, message) in messages.iter_mut().enumerate() {
+            let repeated = index > 0;
+            match message {
+                Message::Write(buf) => {
+                    self.write_address(address, false, repeated)?;
+                    for &byte in buf.iter() {
+                        if !self.write_byte(byte)? {
+                            return Err(Error::Io(io::Error::from_raw_os_error(libc::EREMOTEIO)));
+                        }
+                    }
+                }
+                Message::Read(buf) => {
+                    self.write_address(address, true, repeated)?;
+                    self.read_bytes(buf)?;
+                }
+                Message::ReadBlock(buf) => {
+                    if buf.len() < SMBUS_BLOCK_MAX + 1 {
+                        return Err(Error::InvalidArgument("block read buffer is too small"));
+                    }
+                    self.write_address(address, true, repeated)?;
+                    let count = self.read_byte(true)?;
+                    if count == 0 || usize::from(count) > SMBUS_BLOCK_MAX {
+                        self.read_byte(false)?;
+                        return Err(Error::Protocol("slave sent an invalid block length"));
+                    }
+                    buf[0] = count;
+                    self.read_bytes(&mut buf[1..=usize::from(count)])?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<P: OpenDrainPin> I2cTransport for SoftI2c<P> {
+    /**
+     * Executes the messages as one transaction. The bus is released with a stop condition even if a message fails, unless another master won the arbitration.
+     */
+    fn transfer(&mut self, address: Address, messages: &mut [Message]) -> Result<()> {
+        if let Address::TenBit(0x400..) | Address::SevenBit(0x80..) = address {
+            return Err(Error::InvalidArgument("i2c address is out of range"));
+        }
+        self.arbitration_lost = false;
+        let result = self.transfer_messages(address, messages);
+        if self.arbitration_lost {
+            return result;
+        }
+        let stopped = self.stop();
+        result?;
+        stopped
+    }
+}
@@ -0,0 +1,371 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! Pwm on any gpio output, generated by a dedicated thread.
+//!
+
+use crate::error::{Error, Result};
+use crate::gpio::{DigitalPin, GPIOData, GPIO};
+use crate::pwm::PwmOutput;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/**
+ * Statistics about how late the edges of a [`SoftPwm`] were set compared to their schedule.
+ *
+ * [`SoftPwm`]: ./struct.SoftPwm.html
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct JitterStats {
+    /// Number of measured edges
+    pub samples: u64,
+    /// Average delay of an edge
+    pub mean: Duration,
+    /// Biggest delay of an edge
+    pub max: Duration,
+}
+
+impl JitterStats {
+    fn record(&mut self, delay: Duration) {
+        let total = self.mean.as_secs_f64() * self.samples as f64 + delay.as_secs_f64();
+        self.samples += 1;
+        self.mean = Duration::from_secs_f64(total / self.samples as f64);
+        self.max = self.max.max(delay);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    period: Duration,
+    duty_cycle: Duration,
+}
+
+/// State shared between the handle and the pwm thread
+struct Shared {
+    settings: Mutex<Settings>,
+    jitter: Mutex<JitterStats>,
+    running: AtomicBool,
+}
+
+/// The pin and the first error of the pwm thread. None if the thread stopped before it got the pin.
+type ThreadResult<P> = Option<(P, Result<()>)>;
+
+/**
+ * A software pwm on a gpio output.
+ *
+ * A dedicated thread toggles the pin. Period and duty cycle can be changed while the signal is generated, the change takes effect with the next period.
+ * The accuracy depends on the scheduler of the os. The thread can be run with a real-time priority (needs root permissions) and the delays of the edges are recorded in [`JitterStats`].
+ *
+ * The pin is driven LOW when the pwm is stopped or dropped.
+ *
+ * [`JitterStats`]: ./struct.JitterStats.html
+ */
+pub struct SoftPwm<P: DigitalPin + Send + 'static = GPIO> {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<ThreadResult<P>>>,
+}
+
+impl<P: DigitalPin + Send + 'static> SoftPwm<P> {
+    /**
+     * Calls `with_priority` without real-time priority.
+     *
+     * # Errors
+     * Returns an error if the frequency or duty ratio is invalid.
+     */
+    pub fn new(pin: P, frequency: f64, duty_ratio: f64) -> Result<SoftPwm<P>> {
+        SoftPwm::with_priority(pin, frequency, duty_ratio, None).map_err(|(_, why)| why)
+    }
+
+    /**
+     * Starts a thread that generates the pwm signal on the pin. The pin has to be in write mode.
+     *
+     * If a priority is given, the thread is scheduled with the real-time policy SCHED_FIFO and the priority (1 to 99).
+     *
+     * # Errors
+     * Returns the pin together with the error if the frequency or duty ratio is invalid or if the real-time priority can't be set.
+     */
+    pub fn with_priority(
+        pin: P,
+        frequency: f64,
+        duty_ratio: f64,
+        priority: Option<i32>,
+    ) -> std::result::Result<SoftPwm<P>, (P, Error)> {
+        let period = match check_duty_ratio(duty_ratio).and(period_from_frequency(frequency)) {
+            Ok(period) => period,
+            Err(why) => return Err((pin, why)),
+        };
+        let shared = Arc::new(Shared {
+            settings: Mutex::new(Settings {
+                period,
+                duty_cycle: period.mul_f64(duty_ratio),
+            }),
+            jitter: Mutex::new(JitterStats::default()),
+            running: AtomicBool::new(true),
+        });
+        let (started, start_result) = mpsc::channel();
+        let (handover, handed_over) = mpsc::channel();
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || {
+            if let Some(priority) = priority {
+                if let Err(why) = set_realtime_priority(priority) {
+                    started.send(Err(why)).unwrap_or(());
+                    return None;
+                }
+            }
+            started.send(Ok(())).unwrap_or(());
+            // the pin is handed over after the priority is set, so a failure does not take it
+            let pin: P = handed_over.recv().ok()?;
+            let result = generate(&pin, &thread_shared);
+            let low = pin.set(GPIOData::Low);
+            Some((pin, result.and(low)))
+        });
+        match start_result.recv() {
+            Ok(Ok(())) => match handover.send(pin) {
+                Ok(()) => Ok(SoftPwm {
+                    shared,
+                    thread: Some(thread),
+                }),
+                Err(mpsc::SendError(pin)) => Err((
+                    pin,
+                    Error::ThreadFailed("soft pwm thread stopped before it got the pin"),
+                )),
+            },
+            Ok(Err(why)) => {
+                // the thread returns right after sending the error
+                let _ = thread.join();
+                Err((pin, why))
+            }
+            Err(_) => Err((pin, Error::ThreadFailed("soft pwm thread did not start"))),
+        }
+    }
+
+    /**
+     * Sets the frequency in Hz. The duty ratio is preserved.
+     */
+    pub fn set_frequency(&self, frequency: f64) -> Result<()> {
+        let period = period_from_frequency(frequency)?;
+        let mut settings = self.settings()?;
+        let ratio = ratio(&settings);
+        settings.period = period;
+        settings.duty_cycle = period.mul_f64(ratio);
+        Ok(())
+    }
+
+    /**
+     * Returns the frequency in Hz.
+     */
+    pub fn frequency(&self) -> Result<f64> {
+        Ok(1.0 / self.settings()?.period.as_secs_f64())
+    }
+
+    /**
+     * Sets the duty cycle as ratio of the period. 0.0 is always off and 1.0 is always on.
+     */
+    pub fn set_duty_ratio(&self, duty_ratio: f64) -> Result<()> {
+        check_duty_ratio(duty_ratio)?;
+        let mut settings = self.settings()?;
+        settings.duty_cycle = settings.period.mul_f64(duty_ratio);
+        Ok(())
+    }
+
+    /**
+     * Returns the duty cycle as ratio of the period.
+     */
+    pub fn duty_ratio(&self) -> Result<f64> {
+        Ok(ratio(&*self.settings()?))
+    }
+
+    /**
+     * Sets the length of one pwm cycle. A longer duty cycle is shortened to the period.
+     */
+    pub fn set_period(&self, period: Duration) -> Result<()> {
+        if period == Duration::from_secs(0) {
+            return Err(Error::InvalidArgument("period has to be positive"));
+        }
+        let mut settings = self.settings()?;
+        settings.period = period;
+        settings.duty_cycle = settings.duty_cycle.min(period);
+        Ok(())
+    }
+
+    pub fn period(&self) -> Result<Duration> {
+        Ok(self.settings()?.period)
+    }
+
+    /**
+     * Sets the active time of one pwm cycle. It can't be longer than the period.
+     */
+    pub fn set_duty_cycle(&self, duty_cycle: Duration) -> Result<()> {
+        let mut settings = self.settings()?;
+        if duty_cycle > settings.period {
+            return Err(Error::InvalidArgument(
+                "duty cycle is longer than the period",
+            ));
+        }
+        settings.duty_cycle = duty_cycle;
+        Ok(())
+    }
+
+    pub fn duty_cycle(&self) -> Result<Duration> {
+        Ok(self.settings()?.duty_cycle)
+    }
+
+    /**
+     * Returns the delays of the edges measured since the start or the last reset.
+     */
+    pub fn jitter(&self) -> Result<JitterStats> {
+        Ok(*lock(&self.shared.jitter)?)
+    }
+
+    pub fn reset_jitter(&self) -> Result<()> {
+        *lock(&self.shared.jitter)? = JitterStats::default();
+        Ok(())
+    }
+
+    /**
+     * Stops the signal generation and returns the pin. The pin is driven LOW.
+     *
+     * # Errors
+     * Returns the first error of the pin that occurred while the signal was generated.
+     */
+    pub fn stop(mut self) -> Result<P> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<P> {
+        self.shared.running.store(false, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => {
+                let (pin, result) = thread
+                    .join()
+                    .map_err(|_| Error::ThreadFailed("soft pwm thread panicked"))?
+                    .ok_or(Error::ThreadFailed("soft pwm thread did not get the pin"))?;
+                result.map(|_| pin)
+            }
+            None => Err(Error::ThreadFailed("soft pwm is already stopped")),
+        }
+    }
+
+    fn settings(&self) -> Result<MutexGuard<'_, Settings>> {
+        lock(&self.shared.settings)
+    }
+}
+
+impl<P: DigitalPin + Send + 'static> PwmOutput for SoftPwm<P> {
+    fn set_period(&self, period: Duration) -> Result<()> {
+        SoftPwm::set_period(self, period)
+    }
+
+    fn set_duty_cycle(&self, duty_cycle: Duration) -> Result<()> {
+        SoftPwm::set_duty_cycle(self, duty_cycle)
+    }
+
+    /// Does nothing, the signal is generated from the start
+    fn enable(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Stops the thread and drives the pin LOW
+impl<P: DigitalPin + Send + 'static> Drop for SoftPwm<P> {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            let _ = self.join();
+        }
+    }
+}
+
+/// The loop of the pwm thread. Returns on the first error of the pin.
+fn generate<P: DigitalPin>(pin: &P, shared: &Shared) -> Result<()> {
+    let mut period_start = Instant::now();
+    while shared.running.load(Ordering::SeqCst) {
+        let settings = *lock(&shared.settings)?;
+        if settings.duty_cycle > Duration::from_secs(0) {
+            pin.set(GPIOData::High)?;
+            record(shared, period_start)?;
+        }
+        if settings.duty_cycle < settings.period {
+            let falling_edge = period_start + settings.duty_cycle;
+            sleep_until(falling_edge);
+            pin.set(GPIOData::Low)?;
+            record(shared, falling_edge)?;
+        }
+        period_start += settings.period;
+        let now = Instant::now();
+        if now > period_start + settings.period {
+            // skip the periods that were missed instead of catching up
+            period_start = now;
+        }
+        sleep_until(period_start);
+    }
+    Ok(())
+}
+
+fn record(shared: &Shared, scheduled: Instant) -> Result<()> {
+    let delay = Instant::now().saturating_duration_since(scheduled);
+    lock(&shared.jitter)?.record(delay);
+    Ok(())
+}
+
+fn sleep_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now {
+        thread::sleep(deadline - now);
+    }
+}
+
+fn set_realtime_priority(priority: i32) -> Result<()> {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn ratio(settings: &Settings) -> f64 {
+    settings.duty_cycle.as_secs_f64() / settings.period.as_secs_f64()
+}
+
+fn period_from_frequency(frequency: f64) -> Result<Duration> {
+    if frequency <= 0.0 || !frequency.is_finite() {
+        return Err(Error::InvalidArgument("frequency has to be positive"));
+    }
+    Duration::try_from_secs_f64(1.0 / frequency)
+        .map_err(|_| Error::InvalidArgument("frequency is too low"))
+}
+
+fn check_duty_ratio(duty_ratio: f64) -> Result<()> {
+    if !(0.0..=1.0).contains(&duty_ratio) {
+        return Err(Error::InvalidArgument(
+            "duty ratio has to be between 0 and 1",
+        ));
+    }
+    Ok(())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| Error::LockPoisoned)
+}
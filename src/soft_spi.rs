@@ -0,0 +1,260 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! A spi master that toggles gpio pins in software.
+//!
+
+use crate::error::{Error, Result};
+use crate::gpio::{DigitalPin, GPIOData, GPIO};
+use crate::serial::{ComMode, SpiMode};
+use std::io;
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+
+/**
+ * A bit banged spi master on arbitrary gpio pins.
+ *
+ * It can be used if the pins of the hardware controller are taken or a mode is needed that the controller does not support. The pins for SCLK, MOSI and the optional chip select have to be in write mode, MISO has to be in read mode. The chip select is active low.
+ *
+ * Every bit takes two half periods. The clock polarity and phase are set with [`SpiMode`], the bit order with `set_lsb_first`.
+ *
+ * The `Read` and `Write` implementations behave like the ones of [`SerialPi`]: In full duplex mode the bytes received during `write` are buffered internally and returned by the next calls to `read`. If the buffer is exhausted `read` clocks out zeros.
+ *
+ * Any [`DigitalPin`] implementation can be used instead of [`GPIO`], e.g. to simulate a slave.
+ *
+ * [`SpiMode`]: ../serial/enum.SpiMode.html
+ * [`SerialPi`]: ../serial/struct.SerialPi.html
+ * [`DigitalPin`]: ../gpio/trait.DigitalPin.html
+ * [`GPIO`]: ../gpio/struct.GPIO.html
+ */
+pub struct SoftSpi<P: DigitalPin = GPIO> {
+    sclk: P,
+    mosi: P,
+    miso: P,
+    chip_select: Option<P>,
+    spi_mode: SpiMode,
+    lsb_first: bool,
+    half_period: Duration,
+    pub com_mode: ComMode,
+    read_buffer: Vec<u8>,
+}
+
+impl<P: DigitalPin> SoftSpi<P> {
+    /**
+     * Creates a bit banged spi master with msb first bit order and drives the clock and chip select lines to their idle levels.
+     *
+     * # Errors
+     * Returns an error if one of the pins can't be written.
+     */
+    pub fn new(
+        sclk: P,
+        mosi: P,
+        miso: P,
+        chip_select: Option<P>,
+        spi_mode: SpiMode,
+        communication_mode: ComMode,
+        half_period: Duration,
+    ) -> Result<SoftSpi<P>> {
+        let spi = SoftSpi {
+            sclk,
+            mosi,
+            miso,
+            chip_select,
+            spi_mode,
+            lsb_first: false,
+            half_period,
+            com_mode: communication_mode,
+            read_buffer: Vec::new(),
+        };
+        spi.sclk.set(spi.idle_clock())?;
+        if let Some(ref chip_select) = spi.chip_select {
+            chip_select.set(GPIOData::High)?;
+        }
+        Ok(spi)
+    }
+
+    /**
+     * Changes clock polarity and clock phase. The clock line is driven to the new idle level.
+     */
+    pub fn set_spi_mode(&mut self, spi_mode: SpiMode) -> Result<()> {
+        self.spi_mode = spi_mode;
+        self.sclk.set(self.idle_clock())
+    }
+
+    pub fn spi_mode(&self) -> SpiMode {
+        self.spi_mode
+    }
+
+    /**
+     * Transmits the least significant bit of each byte first if set to true.
+     */
+    pub fn set_lsb_first(&mut self, lsb_first: bool) {
+        self.lsb_first = lsb_first;
+    }
+
+    pub fn lsb_first(&self) -> bool {
+        self.lsb_first
+    }
+
+    /**
+     * Sets the time between two clock edges. The resulting clock is slower, because toggling the pins takes time as well.
+     */
+    pub fn set_half_period(&mut self, half_period: Duration) {
+        self.half_period = half_period;
+    }
+
+    pub fn half_period(&self) -> Duration {
+        self.half_period
+    }
+
+    /**
+     * Does a full duplex transfer of `tx` and stores the received bytes in `rx`. The chip select is asserted for the whole transfer.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if `tx` and `rx` have different lengths or an error of the pins.
+     */
+    pub fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        if tx.len() != rx.len() {
+            return Err(Error::InvalidArgument(
+                "transmit and receive buffer have different lengths",
+            ));
+        }
+        self.select(true)?;
+        let mut result = Ok(());
+        for (sent, received) in tx.iter().zip(rx.iter_mut()) {
+            match self.transfer_byte(*sent) {
+                Ok(byte) => *received = byte,
+                Err(why) => {
+                    result = Err(why);
+                    break;
+                }
+            }
+        }
+        let released = self.select(false);
+        result?;
+        released
+    }
+
+    fn transfer_byte(&self, byte: u8) -> Result<u8> {
+        let mut received = 0_u8;
+        for index in 0..8 {
+            let bit = if self.lsb_first { index } else { 7 - index };
+            let sample = match self.spi_mode {
+                // data is valid on the leading edge
+                SpiMode::Mode0 | SpiMode::Mode2 => {
+                    self.mosi.set(level(byte & (1 << bit) != 0))?;
+                    self.wait();
+                    self.sclk.set(self.active_clock())?;
+                    let sample = self.miso.value()?;
+                    self.wait();
+                    self.sclk.set(self.idle_clock())?;
+                    sample
+                }
+                // data is valid on the trailing edge
+                SpiMode::Mode1 | SpiMode::Mode3 => {
+                    self.sclk.set(self.active_clock())?;
+                    self.mosi.set(level(byte & (1 << bit) != 0))?;
+                    self.wait();
+                    self.sclk.set(self.idle_clock())?;
+                    let sample = self.miso.value()?;
+                    self.wait();
+                    sample
+                }
+            };
+            if sample == GPIOData::High {
+                received |= 1 << bit;
+            }
+        }
+        Ok(received)
+    }
+
+    fn select(&self, selected: bool) -> Result<()> {
+        match self.chip_select {
+            Some(ref chip_select) => chip_select.set(level(!selected)),
+            None => Ok(()),
+        }
+    }
+
+    fn wait(&self) {
+        if self.half_period > Duration::from_secs(0) {
+            thread::sleep(self.half_period);
+        }
+    }
+
+    fn idle_clock(&self) -> GPIOData {
+        match self.spi_mode {
+            SpiMode::Mode0 | SpiMode::Mode1 => GPIOData::Low,
+            SpiMode::Mode2 | SpiMode::Mode3 => GPIOData::High,
+        }
+    }
+
+    fn active_clock(&self) -> GPIOData {
+        level(self.idle_clock() == GPIOData::Low)
+    }
+}
+
+fn level(high: bool) -> GPIOData {
+    if high {
+        GPIOData::High
+    } else {
+        GPIOData::Low
+    }
+}
+
+impl<P: DigitalPin> Read for SoftSpi<P> {
+    /**
+     * Fills buf with the bytes from the internal buffer. If buf.len() is greater then the buffered byte count, the remaining bytes are read from the slave while zeros are sent.
+     */
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buffer_read_count = self.read_buffer.as_slice().read(buf)?;
+        self.read_buffer.drain(0..buffer_read_count);
+        if buffer_read_count < buf.len() {
+            let (_, rest_buffer) = buf.split_at_mut(buffer_read_count);
+            let zeros = vec![0_u8; rest_buffer.len()];
+            self.transfer(&zeros, rest_buffer)?;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl<P: DigitalPin> Write for SoftSpi<P> {
+    /**
+     * Sends buf to the slave. The received bytes are buffered internally if [`ComMode`] is [`ComMode::FullDuplex`].
+     *
+     * [`ComMode`]: ../serial/enum.ComMode.html
+     * [`ComMode::FullDuplex`]: ../serial/enum.ComMode.html#variant.FullDuplex
+     */
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut read_data: Vec<u8> = vec![0_u8; buf.len()];
+        self.transfer(buf, &mut read_data)?;
+        if self.com_mode == ComMode::FullDuplex {
+            self.read_buffer.append(&mut read_data);
+        }
+        Ok(buf.len())
+    }
+
+    /**
+     * Does nothing, every write is transmitted immediately.
+     */
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! Shares one spi controller between several slaves with different configurations.
+//!
+
+use crate::error::{Error, Result};
+use crate::serial::{ChipSelect, ComMode, Device, SerialPi, Speed, SpiMode, Word};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/**
+ * The configuration of a single slave on a shared bus. It is applied to the controller before every transaction of the corresponding [`SpiDevice`].
+ *
+ * [`SpiDevice`]: ./struct.SpiDevice.html
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceConfig {
+    pub device: Device,
+    pub speed: Speed,
+    pub spi_mode: SpiMode,
+    pub bits_per_word: u8,
+    pub lsb_first: bool,
+}
+
+impl DeviceConfig {
+    /**
+     * Creates a configuration with 8 bits per word and msb first bit order.
+     */
+    pub fn new(device: Device, speed: Speed, spi_mode: SpiMode) -> DeviceConfig {
+        DeviceConfig {
+            device,
+            speed,
+            spi_mode,
+            bits_per_word: 8,
+            lsb_first: false,
+        }
+    }
+}
+
+/// An opened chip enable line and the configuration that was applied last.
+/// The flag tells if the hardware chip select was disabled for a gpio chip select.
+struct Port {
+    serial: SerialPi,
+    applied: Option<(DeviceConfig, bool)>,
+}
+
+struct BusState {
+    ports: [Option<Port>; 2],
+}
+
+/**
+ * Owner of the spi controller of the raspberry pi.
+ *
+ * The bus hands out [`SpiDevice`] handles for every slave. Each handle carries its own [`DeviceConfig`]. Before a transaction the bus is locked and the configuration of the device is written to the controller if another configuration was used before.
+ *
+ * The chip enable lines are opened on demand when the first device using them is created.
+ *
+ * [`SpiDevice`]: ./struct.SpiDevice.html
+ * [`DeviceConfig`]: ./struct.DeviceConfig.html
+ */
+pub struct SpiBus {
+    state: Arc<Mutex<BusState>>,
+}
+
+impl SpiBus {
+    /**
+     * Creates a bus without any opened chip enable lines.
+     */
+    pub fn new() -> SpiBus {
+        SpiBus {
+            state: Arc::new(Mutex::new(BusState {
+                ports: [None, None],
+            })),
+        }
+    }
+
+    /**
+     * Creates a handle for a slave with the given configuration.
+     *
+     * # Errors
+     * Returns an error if the chip enable line of the configuration can't be opened. See [`SerialPi::with_capacity`](../serial/struct.SerialPi.html#method.with_capacity).
+     */
+    pub fn device(&self, config: DeviceConfig) -> Result<SpiDevice> {
+        self.open_device(config, None)
+    }
+
+    /**
+     * Creates a handle for a slave that is selected by a gpio instead of a CE pin.
+     * The CE line of the configuration is still used to access the controller, but it is not toggled during the transactions of this device.
+     *
+     * # Errors
+     * Returns an error if the chip enable line of the configuration can't be opened. See [`SerialPi::with_capacity`](../serial/struct.SerialPi.html#method.with_capacity).
+     */
+    pub fn device_with_chip_select(
+        &self,
+        config: DeviceConfig,
+        chip_select: ChipSelect,
+    ) -> Result<SpiDevice> {
+        self.open_device(config, Some(Arc::new(chip_select)))
+    }
+
+    fn open_device(
+        &self,
+        config: DeviceConfig,
+        chip_select: Option<Arc<ChipSelect>>,
+    ) -> Result<SpiDevice> {
+        {
+            let mut state = lock(&self.state)?;
+            let port = &mut state.ports[config.device as usize];
+            if port.is_none() {
+                // the internal buffer of SerialPi is not used by the bus
+                let serial = SerialPi::with_capacity(
+                    config.device,
+                    config.speed,
+                    config.spi_mode,
+                    ComMode::FullDuplex,
+                    0,
+                )?;
+                *port = Some(Port {
+                    serial,
+                    applied: None,
+                });
+            }
+        }
+        Ok(SpiDevice {
+            state: self.state.clone(),
+            config,
+            chip_select,
+        })
+    }
+}
+
+impl Default for SpiBus {
+    fn default() -> Self {
+        SpiBus::new()
+    }
+}
+
+/**
+ * A handle for a single slave on a [`SpiBus`]. Handles can be cloned and moved to other threads.
+ *
+ * Every function locks the bus for the duration of the transaction, so transactions of different devices do not interleave.
+ *
+ * [`SpiBus`]: ./struct.SpiBus.html
+ */
+#[derive(Clone)]
+pub struct SpiDevice {
+    state: Arc<Mutex<BusState>>,
+    config: DeviceConfig,
+    chip_select: Option<Arc<ChipSelect>>,
+}
+
+impl SpiDevice {
+    /**
+     * Returns the configuration that is applied before every transaction.
+     */
+    pub fn config(&self) -> &DeviceConfig {
+        &self.config
+    }
+
+    /**
+     * Locks the bus, applies the configuration of this device and calls `f` with the configured [`SerialPi`].
+     * Use this to issue several transfers without releasing the bus in between.
+     * A gpio chip select is asserted for the whole duration of `f`.
+     *
+     * [`SerialPi`]: ../serial/struct.SerialPi.html
+     */
+    pub fn transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut SerialPi) -> Result<T>,
+    {
+        let mut state = lock(&self.state)?;
+        let port = match state.ports[self.config.device as usize] {
+            Some(ref mut port) => port,
+            None => unreachable!("ports are opened before a device is created"),
+        };
+        let no_cs = self.chip_select.is_some();
+        if port.applied != Some((self.config, no_cs)) {
+            // forget the old configuration in case only a part of the new one can be applied
+            port.applied = None;
+            port.serial.set_speed(self.config.speed)?;
+            port.serial.set_spi_mode(self.config.spi_mode)?;
+            port.serial.set_bits_per_word(self.config.bits_per_word)?;
+            port.serial.set_lsb_first(self.config.lsb_first)?;
+            port.serial.set_no_cs(no_cs)?;
+            port.applied = Some((self.config, no_cs));
+        }
+        match self.chip_select {
+            Some(ref chip_select) => {
+                chip_select.assert()?;
+                let result = f(&mut port.serial);
+                let released = chip_select.release();
+                let value = result?;
+                released?;
+                Ok(value)
+            }
+            None => f(&mut port.serial),
+        }
+    }
+
+    /**
+     * Sends `tx` and ignores the received words.
+     * Use `u8`, `u16` or `u32` words matching the bits per word of the configuration, see [`Word`].
+     *
+     * [`Word`]: ../serial/trait.Word.html
+     */
+    pub fn write<W: Word>(&self, tx: &[W]) -> Result<()> {
+        self.transaction(|serial| serial.write_words(tx))
+    }
+
+    /**
+     * Fills `rx` with words read from the slave.
+     */
+    pub fn read<W: Word>(&self, rx: &mut [W]) -> Result<()> {
+        self.transaction(|serial| serial.read_words(rx))
+    }
+
+    /**
+     * Does a full duplex transfer. `tx` and `rx` must have the same length.
+     */
+    pub fn transfer<W: Word>(&self, tx: &[W], rx: &mut [W]) -> Result<()> {
+        self.transaction(|serial| serial.transfer_words(tx, rx))
+    }
+}
+
+fn lock(state: &Mutex<BusState>) -> Result<MutexGuard<'_, BusState>> {
+    state.lock().map_err(|_| Error::LockPoisoned)
+}
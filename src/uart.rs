@@ -0,0 +1,552 @@
+// This file is part of RustpiIO.
+//
+// Copyright 2018
+//
+// Contributors: Tom Meyer
+//
+// RustpiIO is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RustpiIO is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RustpiIO.  If not, see <http://www.gnu.org/licenses/>
+
+//!
+//! UART serial ports of the raspberry pi, configured with termios.
+//!
+
+use crate::error::{Error, Result};
+use crate::globals::{SERIAL0_PATH, TTYAMA0_PATH, TTYS0_PATH};
+use crate::gpio::{DigitalPin, GPIOData};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+const SER_RS485_ENABLED: u32 = 1;
+const SER_RS485_RTS_ON_SEND: u32 = 1 << 1;
+const SER_RS485_RTS_AFTER_SEND: u32 = 1 << 2;
+
+/// struct serial_rs485 of the kernel
+#[repr(C)]
+#[derive(Default)]
+struct SerialRs485 {
+    flags: u32,
+    delay_rts_before_send: u32,
+    delay_rts_after_send: u32,
+    padding: [u32; 5],
+}
+
+/**
+ * The UART devices of the raspberry pi.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Port {
+    /// The alias of the UART on the gpio header, /dev/serial0
+    Serial0,
+    /// The PL011 UART, /dev/ttyAMA0
+    TtyAMA0,
+    /// The mini UART, /dev/ttyS0
+    TtyS0,
+}
+
+impl Port {
+    pub fn path(self) -> &'static str {
+        match self {
+            Port::Serial0 => SERIAL0_PATH,
+            Port::TtyAMA0 => TTYAMA0_PATH,
+            Port::TtyS0 => TTYS0_PATH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlowControl {
+    None,
+    /// XON/XOFF
+    Software,
+    /// RTS/CTS
+    Hardware,
+}
+
+/**
+ * The line settings of a UART.
+ *
+ * The default is 9600 baud, 8 data bits, no parity, one stop bit (8N1), no flow control, raw mode and blocking reads.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UartConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    /// Passes every byte unchanged. Otherwise the input is line based (canonical mode).
+    pub raw: bool,
+    /// Time a read waits for the first byte. `None` blocks until a byte arrives.
+    /// The resolution is a tenth of a second and the maximum is 25.5 seconds.
+    pub read_timeout: Option<Duration>,
+}
+
+impl Default for UartConfig {
+    fn default() -> UartConfig {
+        UartConfig {
+            baud_rate: 9600,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            raw: true,
+            read_timeout: None,
+        }
+    }
+}
+
+/**
+ * The direction control of a half-duplex RS-485 transceiver.
+ *
+ * The driver enable (DE) and the inverted receiver enable (RE) of the transceiver are tied together and switched to sending around every write.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rs485Config {
+    /// The level of the direction line while sending
+    pub active_high: bool,
+    /// Time between enabling the driver and the first byte
+    pub delay_before_send: Duration,
+    /// Time between the end of the last byte and switching back to receiving
+    pub delay_after_send: Duration,
+}
+
+impl Default for Rs485Config {
+    fn default() -> Rs485Config {
+        Rs485Config {
+            active_high: true,
+            delay_before_send: Duration::from_millis(0),
+            delay_after_send: Duration::from_millis(0),
+        }
+    }
+}
+
+/**
+ * Who switches the direction of the RS-485 transceiver.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rs485Mode {
+    /// The serial driver switches the RTS line
+    Kernel,
+    /// The uart switches a gpio around every write
+    Gpio,
+}
+
+/// A gpio that switches the direction of a RS-485 transceiver
+pub type DirectionPin = Box<dyn DigitalPin + Send>;
+
+struct Rs485 {
+    config: Rs485Config,
+    direction_pin: Option<DirectionPin>,
+}
+
+impl Rs485Config {
+    fn set_sending(&self, pin: &dyn DigitalPin, sending: bool) -> Result<()> {
+        if sending == self.active_high {
+            pin.set(GPIOData::High)
+        } else {
+            pin.set(GPIOData::Low)
+        }
+    }
+}
+
+fn speed(baud_rate: u32) -> Result<libc::speed_t> {
+    Ok(match baud_rate {
+        50 => libc::B50,
+        75 => libc::B75,
+        110 => libc::B110,
+        134 => libc::B134,
+        150 => libc::B150,
+        200 => libc::B200,
+        300 => libc::B300,
+        600 => libc::B600,
+        1200 => libc::B1200,
+        1800 => libc::B1800,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115_200 => libc::B115200,
+        230_400 => libc::B230400,
+        460_800 => libc::B460800,
+        500_000 => libc::B500000,
+        576_000 => libc::B576000,
+        921_600 => libc::B921600,
+        1_000_000 => libc::B1000000,
+        1_152_000 => libc::B1152000,
+        1_500_000 => libc::B1500000,
+        2_000_000 => libc::B2000000,
+        2_500_000 => libc::B2500000,
+        3_000_000 => libc::B3000000,
+        3_500_000 => libc::B3500000,
+        4_000_000 => libc::B4000000,
+        _ => return Err(Error::InvalidArgument("unsupported baud rate")),
+    })
+}
+
+/**
+ * Interface for a UART of the Raspberry Pi.
+ *
+ * The port is configured with termios when it is opened and can be reconfigured with `set_config`.
+ * If a read timeout is configured, a read that receives nothing in time returns `Error::Timeout` (as `io::Error` with `ErrorKind::TimedOut`).
+ *
+ * Half-duplex RS-485 transceivers are supported with `enable_rs485`.
+ *
+ * `open` accepts any terminal, e.g. the slave side of a pseudo-terminal pair to test a protocol without hardware.
+ */
+pub struct Uart {
+    file: File,
+    config: UartConfig,
+    rs485: Option<Rs485>,
+}
+
+impl Uart {
+    /**
+     * Opens one of the UARTs of the raspberry pi.
+     *
+     * # Errors
+     * Can return an error if the port can't be opened or configured. The serial console might still use the UART or it is not enabled. Check the [documentation](https://www.raspberrypi.org/documentation/configuration/uart.md) of the raspberry pi in this case.
+     */
+    pub fn new(port: Port, config: UartConfig) -> Result<Uart> {
+        Uart::open(port.path(), config)
+    }
+
+    /**
+     * Opens the terminal at `path` and applies the configuration.
+     *
+     * # Errors
+     * Can return an error if the terminal can't be opened or configured.
+     */
+    pub fn open<P: AsRef<Path>>(path: P, config: UartConfig) -> Result<Uart> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)
+            .map_err(|source| Error::DeviceNotFound {
+                path: path.as_ref().to_path_buf(),
+                source,
+            })?;
+        let mut uart = Uart {
+            file,
+            config,
+            rs485: None,
+        };
+        uart.set_config(config)?;
+        Ok(uart)
+    }
+
+    /**
+     * Applies new line settings. Data in the output queue is transmitted with the old settings first.
+     *
+     * # Errors
+     * Returns `Error::InvalidArgument` if the baud rate or the read timeout are not supported.
+     */
+    pub fn set_config(&mut self, config: UartConfig) -> Result<()> {
+        let speed = speed(config.baud_rate)?;
+        let (min, time) = match config.read_timeout {
+            None => (1, 0),
+            Some(timeout) => {
+                let tenths = timeout.as_millis().div_ceil(100);
+                if tenths > 255 {
+                    return Err(Error::InvalidArgument(
+                        "read timeout is longer than 25.5 seconds",
+                    ));
+                }
+                (0, tenths as libc::cc_t)
+            }
+        };
+        let fd = self.file.as_raw_fd();
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        unsafe { libc::cfmakeraw(&mut termios) };
+        if !config.raw {
+            termios.c_iflag |= libc::ICRNL;
+            termios.c_lflag |= libc::ICANON;
+        }
+        termios.c_cflag &= !(libc::CSIZE | libc::PARENB | libc::PARODD | libc::CSTOPB);
+        termios.c_cflag &= !libc::CRTSCTS;
+        termios.c_iflag &= !(libc::IXON | libc::IXOFF | libc::IXANY | libc::INPCK);
+        termios.c_cflag |= libc::CREAD | libc::CLOCAL;
+        termios.c_cflag |= match config.data_bits {
+            DataBits::Five => libc::CS5,
+            DataBits::Six => libc::CS6,
+            DataBits::Seven => libc::CS7,
+            DataBits::Eight => libc::CS8,
+        };
+        match config.parity {
+            Parity::None => {}
+            Parity::Even => {
+                termios.c_cflag |= libc::PARENB;
+                termios.c_iflag |= libc::INPCK;
+            }
+            Parity::Odd => {
+                termios.c_cflag |= libc::PARENB | libc::PARODD;
+                termios.c_iflag |= libc::INPCK;
+            }
+        }
+        if config.stop_bits == StopBits::Two {
+            termios.c_cflag |= libc::CSTOPB;
+        }
+        match config.flow_control {
+            FlowControl::None => {}
+            FlowControl::Software => termios.c_iflag |= libc::IXON | libc::IXOFF,
+            FlowControl::Hardware => termios.c_cflag |= libc::CRTSCTS,
+        }
+        termios.c_cc[libc::VMIN] = min;
+        termios.c_cc[libc::VTIME] = time;
+        unsafe {
+            if libc::cfsetispeed(&mut termios, speed) < 0
+                || libc::cfsetospeed(&mut termios, speed) < 0
+                || libc::tcsetattr(fd, libc::TCSADRAIN, &termios) < 0
+            {
+                return Err(Error::last_os_error());
+            }
+        }
+        self.config = config;
+        Ok(())
+    }
+
+    pub fn config(&self) -> UartConfig {
+        self.config
+    }
+
+    /**
+     * Sets only the baud rate and keeps the other settings.
+     */
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        let config = UartConfig {
+            baud_rate,
+            ..self.config
+        };
+        self.set_config(config)
+    }
+
+    /**
+     * Sets only the read timeout and keeps the other settings.
+     */
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) -> Result<()> {
+        let config = UartConfig {
+            read_timeout,
+            ..self.config
+        };
+        self.set_config(config)
+    }
+
+    /**
+     * Switches the direction of a RS-485 transceiver around every write.
+     *
+     * With a `direction_pin` the uart switches the pin itself: It enables the driver, waits `delay_before_send`, writes the data, waits until the last byte left the uart, waits `delay_after_send` and switches back to receiving.
+     * Without a pin the kernel RS-485 mode of the serial driver is used. It switches the RTS line, so the direction input of the transceiver has to be wired to RTS.
+     *
+     * # Errors
+     * Returns `Error::Unsupported` if there is no direction pin and the driver has no RS-485 mode. Can return an error if the direction pin can't be set.
+     * The direction pin is handed back together with the error.
+     */
+    pub fn enable_rs485(
+        &mut self,
+        config: Rs485Config,
+        direction_pin: Option<DirectionPin>,
+    ) -> std::result::Result<Rs485Mode, (Option<DirectionPin>, Error)> {
+        if let Err(why) = self.disable_rs485() {
+            return Err((direction_pin, why));
+        }
+        if let Some(pin) = direction_pin {
+            if let Err(why) = config.set_sending(&*pin, false) {
+                return Err((Some(pin), why));
+            }
+            self.rs485 = Some(Rs485 {
+                config,
+                direction_pin: Some(pin),
+            });
+            return Ok(Rs485Mode::Gpio);
+        }
+        let mut rs485 = SerialRs485 {
+            flags: SER_RS485_ENABLED,
+            delay_rts_before_send: config.delay_before_send.as_millis().min(u32::MAX as u128)
+                as u32,
+            delay_rts_after_send: config.delay_after_send.as_millis().min(u32::MAX as u128) as u32,
+            ..SerialRs485::default()
+        };
+        rs485.flags |= if config.active_high {
+            SER_RS485_RTS_ON_SEND
+        } else {
+            SER_RS485_RTS_AFTER_SEND
+        };
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), libc::TIOCSRS485, &rs485) } < 0 {
+            let error = io::Error::last_os_error();
+            return Err((
+                None,
+                match error.raw_os_error() {
+                    Some(libc::ENOTTY) | Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) => {
+                        Error::Unsupported("the serial driver has no RS-485 mode")
+                    }
+                    _ => Error::Io(error),
+                },
+            ));
+        }
+        self.rs485 = Some(Rs485 {
+            config,
+            direction_pin: None,
+        });
+        Ok(Rs485Mode::Kernel)
+    }
+
+    /**
+     * Stops switching the direction of a RS-485 transceiver and returns the direction pin if there was one.
+     * A direction pin is left in receive mode.
+     */
+    pub fn disable_rs485(&mut self) -> Result<Option<DirectionPin>> {
+        match self.rs485.take() {
+            None => Ok(None),
+            Some(Rs485 {
+                direction_pin: Some(pin),
+                ..
+            }) => Ok(Some(pin)),
+            Some(_) => {
+                let rs485 = SerialRs485::default();
+                if unsafe { libc::ioctl(self.file.as_raw_fd(), libc::TIOCSRS485, &rs485) } < 0 {
+                    return Err(Error::last_os_error());
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /**
+     * Returns who switches the direction of the RS-485 transceiver or `None` if RS-485 is disabled.
+     */
+    pub fn rs485_mode(&self) -> Option<Rs485Mode> {
+        self.rs485.as_ref().map(|rs485| match rs485.direction_pin {
+            Some(_) => Rs485Mode::Gpio,
+            None => Rs485Mode::Kernel,
+        })
+    }
+
+    /**
+     * Returns true if the serial driver has a RS-485 mode.
+     */
+    pub fn kernel_rs485_supported(&self) -> bool {
+        let mut rs485 = SerialRs485::default();
+        unsafe { libc::ioctl(self.file.as_raw_fd(), libc::TIOCGRS485, &mut rs485) == 0 }
+    }
+
+    /**
+     * Blocks until every written byte has been transmitted.
+     */
+    pub fn drain(&self) -> Result<()> {
+        if unsafe { libc::tcdrain(self.file.as_raw_fd()) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /**
+     * Discards received bytes that have not been read yet.
+     */
+    pub fn clear_input(&self) -> Result<()> {
+        if unsafe { libc::tcflush(self.file.as_raw_fd(), libc::TCIFLUSH) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /**
+     * Discards written bytes that have not been transmitted yet.
+     */
+    pub fn clear_output(&self) -> Result<()> {
+        if unsafe { libc::tcflush(self.file.as_raw_fd(), libc::TCOFLUSH) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Read for Uart {
+    /**
+     * Reads the bytes that are available, waiting for at least one.
+     *
+     * # Errors
+     * Returns `Error::Timeout` if a read timeout is configured and no byte arrived in time.
+     */
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.file.read(buf)?;
+        if count == 0 && !buf.is_empty() && self.config.read_timeout.is_some() {
+            return Err(Error::Timeout("uart read timed out").into());
+        }
+        Ok(count)
+    }
+}
+
+impl Write for Uart {
+    /**
+     * Writes the bytes to the output queue.
+     *
+     * If a gpio switches a RS-485 transceiver, the whole buffer is written and transmitted before the write returns.
+     */
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let rs485 = match &self.rs485 {
+            Some(rs485) => rs485,
+            None => return self.file.write(buf),
+        };
+        let pin = match &rs485.direction_pin {
+            Some(pin) => &**pin,
+            None => return self.file.write(buf),
+        };
+        rs485.config.set_sending(pin, true)?;
+        thread::sleep(rs485.config.delay_before_send);
+        let result = (&self.file).write_all(buf).and_then(|_| Ok(self.drain()?));
+        thread::sleep(rs485.config.delay_after_send);
+        rs485.config.set_sending(pin, false)?;
+        result.map(|_| buf.len())
+    }
+
+    /**
+     * Blocks until every written byte has been transmitted.
+     */
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.drain()?)
+    }
+}
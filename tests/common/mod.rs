@@ -0,0 +1,72 @@
+//! Helpers shared by the integration tests. Every test crate uses only some of them.
+#![allow(dead_code)]
+
+use std::fs::{self, File};
+use std::ops::Deref;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// Opens a pseudo-terminal pair in raw mode and returns the master side and the path of the slave side.
+/// The slave descriptor is closed, the device under test opens its own one.
+pub fn pty() -> (File, PathBuf) {
+    let (mut master, mut slave) = (0, 0);
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    assert_eq!(opened, 0);
+    let path = std::fs::read_link(format!("/proc/self/fd/{}", slave)).unwrap();
+    let master = unsafe { File::from_raw_fd(master) };
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        assert_eq!(libc::tcgetattr(master.as_raw_fd(), &mut termios), 0);
+        libc::cfmakeraw(&mut termios);
+        assert_eq!(
+            libc::tcsetattr(master.as_raw_fd(), libc::TCSANOW, &termios),
+            0
+        );
+        libc::close(slave);
+    }
+    (master, path)
+}
+
+/// A directory in the temp dir that is removed together with its content when it is dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates the empty directory `rustpi_<prefix>_<name>_<pid>`. Leftovers of an aborted run are removed first.
+    pub fn new(prefix: &str, name: &str) -> TempDir {
+        let path =
+            std::env::temp_dir().join(format!("rustpi_{}_{}_{}", prefix, name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // a test might have removed it already
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,208 @@
+extern crate rustpi_io;
+
+use rustpi_io::i2c::{self, Address, I2cPi, I2cTransport, Message};
+use rustpi_io::Error;
+use std::collections::VecDeque;
+use std::io;
+
+/// A message as it was sent to the mock bus.
+#[derive(Debug, PartialEq)]
+enum Sent {
+    Write(Vec<u8>),
+    Read(usize),
+    ReadBlock(usize),
+}
+
+/// Records every transaction and answers reads with the queued responses.
+#[derive(Default)]
+struct MockBus {
+    transactions: Vec<(Address, Vec<Sent>)>,
+    responses: VecDeque<Vec<u8>>,
+}
+
+impl I2cTransport for MockBus {
+    fn transfer(&mut self, address: Address, messages: &mut [Message]) -> rustpi_io::Result<()> {
+        let mut sent = Vec::new();
+        for message in messages.iter_mut() {
+            match message {
+                Message::Write(buf) => sent.push(Sent::Write(buf.to_vec())),
+                Message::Read(buf) => {
+                    sent.push(Sent::Read(buf.len()));
+                    let response = self.responses.pop_front().expect("no response queued");
+                    buf.copy_from_slice(&response);
+                }
+                Message::ReadBlock(buf) => {
+                    sent.push(Sent::ReadBlock(buf.len()));
+                    let response = self.responses.pop_front().expect("no response queued");
+                    buf[..response.len()].copy_from_slice(&response);
+                }
+            }
+        }
+        self.transactions.push((address, sent));
+        Ok(())
+    }
+}
+
+fn i2c_pi() -> I2cPi<MockBus> {
+    I2cPi::with_transport(MockBus::default(), Address::SevenBit(0x48)).unwrap()
+}
+
+fn last_transaction(pi: &I2cPi<MockBus>) -> &(Address, Vec<Sent>) {
+    pi.transport().transactions.last().unwrap()
+}
+
+#[test]
+fn word_data_is_little_endian() {
+    let mut pi = i2c_pi();
+    pi.smbus_write_word_data(0x02, 0x1234).unwrap();
+    assert_eq!(
+        last_transaction(&pi),
+        &(
+            Address::SevenBit(0x48),
+            vec![Sent::Write(vec![0x02, 0x34, 0x12])]
+        )
+    );
+
+    pi.transport_mut().responses.push_back(vec![0xCD, 0xAB]);
+    assert_eq!(pi.smbus_read_word_data(0x03).unwrap(), 0xABCD);
+    assert_eq!(
+        last_transaction(&pi).1,
+        vec![Sent::Write(vec![0x03]), Sent::Read(2)]
+    );
+}
+
+#[test]
+fn byte_data() {
+    let mut pi = i2c_pi();
+    pi.smbus_write_byte_data(0x10, 0x7F).unwrap();
+    assert_eq!(last_transaction(&pi).1, vec![Sent::Write(vec![0x10, 0x7F])]);
+    pi.transport_mut().responses.push_back(vec![0x42]);
+    assert_eq!(pi.smbus_read_byte_data(0x11).unwrap(), 0x42);
+    assert_eq!(
+        last_transaction(&pi).1,
+        vec![Sent::Write(vec![0x11]), Sent::Read(1)]
+    );
+    pi.smbus_quick(false).unwrap();
+    assert_eq!(last_transaction(&pi).1, vec![Sent::Write(vec![])]);
+}
+
+#[test]
+fn block_write_is_prefixed_with_its_length() {
+    let mut pi = i2c_pi();
+    pi.smbus_write_block_data(0x20, &[1, 2, 3]).unwrap();
+    assert_eq!(
+        last_transaction(&pi).1,
+        vec![Sent::Write(vec![0x20, 3, 1, 2, 3])]
+    );
+    pi.smbus_write_block_data(0x21, &[]).unwrap();
+    assert_eq!(last_transaction(&pi).1, vec![Sent::Write(vec![0x21, 0])]);
+
+    match pi.smbus_write_block_data(0x22, &[0; 33]) {
+        Err(Error::InvalidArgument(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(pi.transport().transactions.len(), 2);
+}
+
+#[test]
+fn block_read_uses_the_count_byte() {
+    let mut pi = i2c_pi();
+    pi.transport_mut()
+        .responses
+        .push_back(vec![3, 0xA, 0xB, 0xC, 0xD]);
+    assert_eq!(pi.smbus_read_block_data(0x30).unwrap(), vec![0xA, 0xB, 0xC]);
+    // the buffer has room for the count byte and 32 data bytes
+    assert_eq!(
+        last_transaction(&pi).1,
+        vec![Sent::Write(vec![0x30]), Sent::ReadBlock(33)]
+    );
+
+    let mut block = vec![32];
+    block.extend(0..32);
+    pi.transport_mut().responses.push_back(block);
+    assert_eq!(pi.smbus_read_block_data(0x31).unwrap().len(), 32);
+}
+
+#[test]
+fn block_read_rejects_long_counts() {
+    let mut pi = i2c_pi();
+    pi.transport_mut().responses.push_back(vec![33]);
+    match pi.smbus_read_block_data(0x30) {
+        Err(Error::Protocol(_)) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn addresses_are_checked() {
+    assert!(I2cPi::with_transport(MockBus::default(), Address::SevenBit(0x80)).is_err());
+    let mut pi = i2c_pi();
+    assert!(pi.set_slave_address(Address::TenBit(0x400)).is_err());
+    assert_eq!(pi.slave_address(), Address::SevenBit(0x48));
+    pi.set_slave_address(Address::TenBit(0x3FF)).unwrap();
+    pi.smbus_write_byte(1).unwrap();
+    assert_eq!(last_transaction(&pi).0, Address::TenBit(0x3FF));
+}
+
+/// Acknowledges the present addresses and fails all others with the given errno.
+struct ScanBus {
+    present: Vec<u8>,
+    errno: i32,
+    probes: Vec<(u8, bool)>,
+}
+
+impl I2cTransport for ScanBus {
+    fn transfer(&mut self, address: Address, messages: &mut [Message]) -> rustpi_io::Result<()> {
+        let address = match address {
+            Address::SevenBit(address) => address,
+            Address::TenBit(_) => panic!("scan uses 7 bit addresses"),
+        };
+        let read = matches!(messages, [Message::Read(_)]);
+        self.probes.push((address, read));
+        if self.present.contains(&address) {
+            Ok(())
+        } else {
+            Err(Error::Io(io::Error::from_raw_os_error(self.errno)))
+        }
+    }
+}
+
+fn scan_bus(errno: i32) -> ScanBus {
+    ScanBus {
+        present: vec![0x08, 0x50, 0x77],
+        errno,
+        probes: Vec::new(),
+    }
+}
+
+#[test]
+fn scan_treats_missing_acknowledges_as_absent() {
+    for &errno in [libc::ENXIO, libc::EREMOTEIO, libc::ETIMEDOUT].iter() {
+        let mut bus = scan_bus(errno);
+        assert_eq!(i2c::scan(&mut bus).unwrap(), vec![0x08, 0x50, 0x77]);
+        assert_eq!(bus.probes.len(), 0x70);
+    }
+}
+
+#[test]
+fn scan_probes_eeprom_addresses_with_a_read() {
+    let mut bus = scan_bus(libc::ENXIO);
+    i2c::scan(&mut bus).unwrap();
+    for &(address, read) in bus.probes.iter() {
+        let expected = (0x30..=0x37).contains(&address) || (0x50..=0x5F).contains(&address);
+        assert_eq!(read, expected, "address {:#x}", address);
+    }
+}
+
+#[test]
+fn scan_returns_other_errors() {
+    for &errno in [libc::EOPNOTSUPP, libc::EBADF, libc::EPERM].iter() {
+        let mut bus = scan_bus(errno);
+        match i2c::scan(&mut bus) {
+            Err(Error::Io(error)) => assert_eq!(error.raw_os_error(), Some(errno)),
+            other => panic!("errno {}: {:?}", errno, other),
+        }
+        // the scan stops at the first address without a slave
+        assert_eq!(bus.probes.len(), 2);
+    }
+}
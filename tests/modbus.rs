@@ -0,0 +1,222 @@
+extern crate rustpi_io;
+
+mod common;
+
+use common::pty;
+use rustpi_io::modbus::*;
+use rustpi_io::uart::{Uart, UartConfig};
+use rustpi_io::Error;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Appends the crc to the frame
+fn frame(mut data: Vec<u8>) -> Vec<u8> {
+    let crc = crc16(&data);
+    data.extend_from_slice(&crc.to_le_bytes());
+    data
+}
+
+/// Opens a pseudo-terminal pair and returns the raw master side and a master on the slave side.
+/// Closing the raw side hangs up the line, so the simulated slaves hand it back when they are done.
+fn simulated_line() -> (File, RtuMaster<Uart>) {
+    let (line, path) = pty();
+    let config = UartConfig {
+        baud_rate: 19200,
+        read_timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let uart = Uart::open(&path, config).unwrap();
+    (line, RtuMaster::with_uart(uart))
+}
+
+fn expect(line: &mut File, request: Vec<u8>) {
+    let mut received = vec![0_u8; request.len() + 2];
+    line.read_exact(&mut received).unwrap();
+    assert_eq!(received, frame(request));
+}
+
+#[test]
+fn crc_of_a_request() {
+    assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), 0xCDC5);
+    assert_eq!(frame_silence(9600), Duration::from_micros(4010));
+    assert_eq!(frame_silence(115_200), Duration::from_micros(1750));
+}
+
+#[test]
+fn requests_and_responses() {
+    let (mut line, mut master) = simulated_line();
+    let slave = thread::spawn(move || {
+        expect(&mut line, vec![1, 0x03, 0, 0x10, 0, 2]);
+        line.write_all(&frame(vec![1, 0x03, 4, 0, 1, 0, 2]))
+            .unwrap();
+        expect(&mut line, vec![1, 0x01, 0, 0, 0, 9]);
+        line.write_all(&frame(vec![1, 0x01, 2, 0b101, 0b1]))
+            .unwrap();
+        expect(&mut line, vec![1, 0x05, 0, 3, 0xFF, 0]);
+        line.write_all(&frame(vec![1, 0x05, 0, 3, 0xFF, 0]))
+            .unwrap();
+        expect(&mut line, vec![1, 0x10, 0, 5, 0, 2, 4, 0x12, 0x34, 0, 7]);
+        line.write_all(&frame(vec![1, 0x10, 0, 5, 0, 2])).unwrap();
+        expect(&mut line, vec![1, 0x0F, 0, 0, 0, 10, 2, 0b1000_0001, 0b10]);
+        line.write_all(&frame(vec![1, 0x0F, 0, 0, 0, 10])).unwrap();
+        line
+    });
+    assert_eq!(
+        master.read_holding_registers(1, 0x10, 2).unwrap(),
+        vec![1, 2]
+    );
+    assert_eq!(
+        master.read_coils(1, 0, 9).unwrap(),
+        vec![true, false, true, false, false, false, false, false, true]
+    );
+    master.write_single_coil(1, 3, true).unwrap();
+    master.write_multiple_registers(1, 5, &[0x1234, 7]).unwrap();
+    let coils = [
+        true, false, false, false, false, false, false, true, false, true,
+    ];
+    master.write_multiple_coils(1, 0, &coils).unwrap();
+    slave.join().unwrap();
+}
+
+#[test]
+fn exception_response() {
+    let (mut line, mut master) = simulated_line();
+    let slave = thread::spawn(move || {
+        expect(&mut line, vec![1, 0x06, 0, 0, 0, 5]);
+        line.write_all(&frame(vec![1, 0x86, 2])).unwrap();
+        line
+    });
+    match master.write_single_register(1, 0, 5) {
+        Err(Error::ModbusException(exception)) => {
+            assert_eq!(exception.function, 0x06);
+            assert_eq!(exception.code, ExceptionCode::IllegalDataAddress);
+        }
+        other => panic!("{:?}", other),
+    }
+    slave.join().unwrap();
+}
+
+#[test]
+fn corrupted_response_is_retried() {
+    let (mut line, mut master) = simulated_line();
+    let slave = thread::spawn(move || {
+        let mut corrupted = frame(vec![1, 0x03, 4, 0, 1, 0, 2]);
+        corrupted[4] ^= 1;
+        expect(&mut line, vec![1, 0x03, 0, 0x10, 0, 2]);
+        line.write_all(&corrupted).unwrap();
+        expect(&mut line, vec![1, 0x03, 0, 0x10, 0, 2]);
+        line.write_all(&frame(vec![1, 0x03, 4, 0, 1, 0, 2]))
+            .unwrap();
+        line
+    });
+    assert_eq!(
+        master.read_holding_registers(1, 0x10, 2).unwrap(),
+        vec![1, 2]
+    );
+    slave.join().unwrap();
+}
+
+#[test]
+fn retry_discards_the_rest_of_a_failed_response() {
+    let (mut line, mut master) = simulated_line();
+    master.set_retries(1);
+    let slave = thread::spawn(move || {
+        expect(&mut line, vec![1, 0x04, 0, 0, 0, 1]);
+        // a response from another slave followed by more bytes than the master reads
+        let mut answer = frame(vec![2, 0x04, 2, 0, 9]);
+        answer.extend_from_slice(&frame(vec![2, 0x04, 2, 0, 9]));
+        line.write_all(&answer).unwrap();
+        expect(&mut line, vec![1, 0x04, 0, 0, 0, 1]);
+        line.write_all(&frame(vec![1, 0x04, 2, 0, 7])).unwrap();
+        line
+    });
+    assert_eq!(master.read_input_registers(1, 0, 1).unwrap(), vec![7]);
+    slave.join().unwrap();
+}
+
+#[test]
+fn missing_response_times_out_after_the_retries() {
+    let (mut line, mut master) = simulated_line();
+    master.set_retries(1);
+    let slave = thread::spawn(move || {
+        expect(&mut line, vec![1, 0x04, 0, 0, 0, 1]);
+        expect(&mut line, vec![1, 0x04, 0, 0, 0, 1]);
+        line
+    });
+    let error = master.read_input_registers(1, 0, 1).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::TimedOut);
+    slave.join().unwrap();
+}
+
+#[test]
+fn broadcast_is_not_answered() {
+    let (mut line, mut master) = simulated_line();
+    master.write_single_register(BROADCAST, 1, 2).unwrap();
+    expect(&mut line, vec![0, 0x06, 0, 1, 0, 2]);
+    match master.read_holding_registers(BROADCAST, 0, 1) {
+        Err(Error::InvalidArgument(_)) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Write,
+    Clear,
+}
+
+/// A line that never answers and records when it was written and cleared.
+#[derive(Default)]
+struct SilentLine {
+    events: Vec<(Event, Instant)>,
+}
+
+impl Read for SilentLine {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(ErrorKind::TimedOut, "no response"))
+    }
+}
+
+impl Write for SilentLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.events.push((Event::Write, Instant::now()));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl RtuLine for SilentLine {
+    fn clear_input(&mut self) -> rustpi_io::Result<()> {
+        self.events.push((Event::Clear, Instant::now()));
+        Ok(())
+    }
+}
+
+#[test]
+fn retry_waits_for_the_frame_silence_and_clears_the_input() {
+    let mut master = RtuMaster::new(SilentLine::default(), 9600);
+    master.set_frame_silence(Duration::from_millis(20));
+    assert!(master.read_holding_registers(1, 0, 1).is_err());
+    let events = &master.line().events;
+    let kinds: Vec<&Event> = events.iter().map(|(event, _)| event).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            &Event::Write,
+            &Event::Clear,
+            &Event::Write,
+            &Event::Clear,
+            &Event::Write
+        ]
+    );
+    for pair in events.windows(2) {
+        if pair[0].0 == Event::Write {
+            assert!(pair[1].1 - pair[0].1 >= Duration::from_millis(20));
+        }
+    }
+}
@@ -0,0 +1,167 @@
+extern crate rustpi_io;
+
+mod common;
+
+use common::TempDir;
+use rustpi_io::onewire::{DeviceId, OneWire, Resolution, FAMILY_DS18B20};
+use rustpi_io::Error;
+use std::fs;
+use std::path::Path;
+
+const SENSOR: &str = "28-0316a2793dff";
+
+/// Creates a devices directory with a bus master, a DS18S20 and a DS18B20 like the w1 driver does.
+fn fake_tree(name: &str) -> TempDir {
+    let root = TempDir::new("w1", name);
+    for device in ["w1_bus_master1", "10-000802b4ec10", SENSOR].iter() {
+        fs::create_dir_all(root.join(device)).unwrap();
+    }
+    write_scratchpad(&root, "72 01 4b 46 7f ff 0e 10 57", "YES", "23125");
+    root
+}
+
+fn write_scratchpad(root: &Path, scratchpad: &str, crc: &str, temperature: &str) {
+    fs::write(
+        root.join(SENSOR).join("w1_slave"),
+        format!(
+            "{0} : crc=57 {1}\n{0} t={2}\n",
+            scratchpad, crc, temperature
+        ),
+    )
+    .unwrap();
+}
+
+fn sensor_id() -> DeviceId {
+    SENSOR.parse().unwrap()
+}
+
+#[test]
+fn device_ids() {
+    let id = sensor_id();
+    assert_eq!(id.family, FAMILY_DS18B20);
+    assert_eq!(id.serial, 0x0316_a279_3dff);
+    assert_eq!(id.to_string(), SENSOR);
+    for invalid in [
+        "w1_bus_master1",
+        "28-0316a2793df",
+        "2-0316a2793dff",
+        "zz-0316a2793dff",
+    ]
+    .iter()
+    {
+        match invalid.parse::<DeviceId>() {
+            Err(Error::Parse { .. }) => {}
+            other => panic!("{}: {:?}", invalid, other),
+        }
+    }
+}
+
+#[test]
+fn list_devices() {
+    let root = fake_tree("list");
+    let w1 = OneWire::with_sysfs_root(&root).unwrap();
+    let devices = w1.devices().unwrap();
+    // sorted and without the bus master
+    assert_eq!(
+        devices,
+        vec!["10-000802b4ec10".parse().unwrap(), sensor_id()]
+    );
+    assert_eq!(
+        w1.devices_of_family(FAMILY_DS18B20).unwrap(),
+        vec![sensor_id()]
+    );
+    assert!(w1.devices_of_family(0x3B).unwrap().is_empty());
+}
+
+#[test]
+fn missing_root() {
+    let root = std::env::temp_dir().join(format!("rustpi_w1_missing_{}", std::process::id()));
+    match OneWire::with_sysfs_root(&root) {
+        Err(Error::DeviceNotFound { path, .. }) => assert_eq!(path, root),
+        Err(why) => panic!("{:?}", why),
+        Ok(_) => panic!("opened a missing root"),
+    }
+}
+
+#[test]
+fn ds18b20_is_checked() {
+    let root = fake_tree("check");
+    let w1 = OneWire::with_sysfs_root(&root).unwrap();
+    match w1.ds18b20("10-000802b4ec10".parse().unwrap()) {
+        Err(Error::InvalidArgument(_)) => {}
+        Err(why) => panic!("{:?}", why),
+        Ok(_) => panic!("a DS18S20 is no DS18B20"),
+    }
+    match w1.ds18b20("28-000000000001".parse().unwrap()) {
+        Err(Error::DeviceNotFound { .. }) => {}
+        Err(why) => panic!("{:?}", why),
+        Ok(_) => panic!("found a sensor that is not on the bus"),
+    }
+}
+
+#[test]
+fn read_temperature() {
+    let root = fake_tree("temperature");
+    let sensor = OneWire::with_sysfs_root(&root)
+        .unwrap()
+        .ds18b20(sensor_id())
+        .unwrap();
+    assert_eq!(sensor.id(), sensor_id());
+    assert_eq!(sensor.temperature_millicelsius().unwrap(), 23125);
+    assert_eq!(sensor.temperature().unwrap(), 23.125);
+
+    write_scratchpad(&root, "ec ff 4b 46 7f ff 0c 10 f4", "YES", "-1250");
+    assert_eq!(sensor.temperature().unwrap(), -1.25);
+
+    write_scratchpad(&root, "ec ff 4b 46 7f ff 0c 10 f4", "NO", "-1250");
+    match sensor.temperature() {
+        Err(Error::Protocol(_)) => {}
+        other => panic!("{:?}", other),
+    }
+
+    fs::write(root.join(SENSOR).join("w1_slave"), "").unwrap();
+    assert!(sensor.temperature().is_err());
+}
+
+#[test]
+fn resolution_from_the_scratchpad() {
+    let root = fake_tree("scratchpad");
+    let sensor = OneWire::with_sysfs_root(&root)
+        .unwrap()
+        .ds18b20(sensor_id())
+        .unwrap();
+    assert_eq!(sensor.resolution().unwrap(), Resolution::Bits12);
+    write_scratchpad(&root, "72 01 4b 46 1f ff 0e 10 57", "YES", "23125");
+    assert_eq!(sensor.resolution().unwrap(), Resolution::Bits9);
+
+    // older kernels take the resolution written to w1_slave
+    sensor.set_resolution(Resolution::Bits10).unwrap();
+    assert_eq!(
+        fs::read_to_string(root.join(SENSOR).join("w1_slave")).unwrap(),
+        "10"
+    );
+}
+
+#[test]
+fn resolution_attribute() {
+    let root = fake_tree("attribute");
+    fs::write(root.join(SENSOR).join("resolution"), "11\n").unwrap();
+    let sensor = OneWire::with_sysfs_root(&root)
+        .unwrap()
+        .ds18b20(sensor_id())
+        .unwrap();
+    assert_eq!(sensor.resolution().unwrap(), Resolution::Bits11);
+    sensor.set_resolution(Resolution::Bits9).unwrap();
+    assert_eq!(
+        fs::read_to_string(root.join(SENSOR).join("resolution")).unwrap(),
+        "9"
+    );
+    assert_eq!(sensor.resolution().unwrap(), Resolution::Bits9);
+    // the scratchpad is left alone
+    assert!(fs::read_to_string(root.join(SENSOR).join("w1_slave"))
+        .unwrap()
+        .contains("t=23125"));
+
+    fs::write(root.join(SENSOR).join("resolution"), "8\n").unwrap();
+    assert!(sensor.resolution().is_err());
+}
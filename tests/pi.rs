@@ -0,0 +1,267 @@
+extern crate rustpi_io;
+
+mod common;
+
+use common::TempDir;
+use rustpi_io::pi::*;
+use rustpi_io::Error;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// The fixtures are written in the format of the kernel output. They are no captures, the serial numbers are placeholders.
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/cpuinfo")
+        .join(name)
+}
+
+fn info_of(name: &str) -> rustpi_io::Result<RaspberryInfo> {
+    raspberry_info_from_reader(File::open(fixture(name)).unwrap())
+}
+
+/// Creates a proc directory with the cpuinfo fixture and an empty device tree.
+fn fake_proc(name: &str, cpuinfo: &str) -> TempDir {
+    let root = TempDir::new("pi", name);
+    fs::create_dir_all(root.join("device-tree/system")).unwrap();
+    fs::copy(fixture(cpuinfo), root.join("cpuinfo")).unwrap();
+    root
+}
+
+#[test]
+fn old_style_boards() {
+    let info = info_of("pi1_b_rev2").unwrap();
+    assert_eq!(info.revision, 0x000e);
+    assert_eq!(info.style, RevisionStyle::Old);
+    assert_eq!(info.model, Type::B);
+    assert_eq!(info.memory, MemorySize::MB512);
+    assert_eq!(info.manufacturer, Manufacturer::SonyUK);
+    assert_eq!(info.processor, Processor::BCM2835);
+    assert_eq!(info.to_string(), "B rev 2.0, 512MB, BCM2835, SonyUK (e)");
+    assert!(!info.warranty_void);
+
+    let info = info_of("pi1_b_rev1_overvolted").unwrap();
+    assert_eq!(info.revision, 0x100_0002);
+    assert_eq!(info.model, Type::B);
+    assert_eq!(info.manufacturer, Manufacturer::Egoman);
+    assert_eq!(info.pcb_revision, 0);
+    assert!(info.warranty_void);
+}
+
+#[test]
+fn new_style_boards() {
+    let info = info_of("pi3_b").unwrap();
+    assert_eq!(info.style, RevisionStyle::New);
+    assert_eq!(
+        info.to_string(),
+        "B3 rev 1.2, 1024MB, BCM2837, SonyUK (a02082)"
+    );
+
+    let info = info_of("pi4_b").unwrap();
+    assert_eq!(
+        info.to_string(),
+        "B4 rev 1.1, 4096MB, BCM2711, SonyUK (c03111)"
+    );
+    assert!(!info.warranty_void);
+
+    let info = info_of("pi5_b_arm64").unwrap();
+    assert_eq!(info.model, Type::B5);
+    assert_eq!(info.memory, MemorySize::MB8192);
+    assert_eq!(info.processor, Processor::BCM2712);
+    assert_eq!(info.pcb_revision, 0);
+}
+
+#[test]
+fn missing_revision_line() {
+    // mainline 64-bit kernels don't print the revision
+    match info_of("pi4_b_arm64_mainline") {
+        Err(Error::MissingField { field }) => assert_eq!(field, "Revision"),
+        other => panic!("{:?}", other),
+    }
+    match raspberry_info_from_str("") {
+        Err(Error::MissingField { .. }) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn revision_is_not_hexadecimal() {
+    match info_of("revision_not_hex") {
+        Err(Error::Parse { input, .. }) => assert_eq!(input, "a0208g"),
+        other => panic!("{:?}", other),
+    }
+    assert!("zz".parse::<RaspberryInfo>().is_err());
+}
+
+#[test]
+fn unknown_old_style_revision() {
+    match info_of("revision_unknown_old_style") {
+        Err(Error::UnsupportedRevision { revision }) => assert_eq!(revision, 0x16),
+        other => panic!("{:?}", other),
+    }
+    // 0x0a and 0x0b were never used
+    assert!(decode_revision(0x0a).is_err());
+    assert!(decode_revision(0x0b).is_err());
+}
+
+struct FailingReader;
+
+impl Read for FailingReader {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::other("read failed"))
+    }
+}
+
+#[test]
+fn unreadable_cpuinfo() {
+    match raspberry_info_from_reader(FailingReader) {
+        Err(Error::Io(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    // not valid utf-8
+    assert!(raspberry_info_from_reader(&[0xFF, 0xFE][..]).is_err());
+}
+
+#[test]
+fn missing_cpuinfo() {
+    let root = std::env::temp_dir().join(format!("rustpi_pi_missing_{}", std::process::id()));
+    match board_info_with_proc_root(&root) {
+        Err(Error::DeviceNotFound { path, .. }) => assert_eq!(path, root.join("cpuinfo")),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn board_info_from_cpuinfo() {
+    let root = fake_proc("cpuinfo", "pi3_b");
+    let board = board_info_with_proc_root(&root).unwrap();
+    assert_eq!(board.revision, info_of("pi3_b").unwrap());
+    assert_eq!(board.serial.as_deref(), Some("00000000deadbeef"));
+    assert_eq!(board.hardware.as_deref(), Some("BCM2835"));
+    assert_eq!(
+        board.model.as_deref(),
+        Some("Raspberry Pi 3 Model B Rev 1.2")
+    );
+}
+
+#[test]
+fn board_info_from_the_device_tree() {
+    let root = fake_proc("device_tree", "pi4_b_arm64_mainline");
+    match board_info_with_proc_root(&root) {
+        Err(Error::MissingField { .. }) => {}
+        other => panic!("{:?}", other),
+    }
+    let device_tree = root.join("device-tree");
+    fs::write(
+        device_tree.join("system/linux,revision"),
+        0xc0_3111_u32.to_be_bytes(),
+    )
+    .unwrap();
+    fs::write(device_tree.join("serial-number"), b"10000000c0ffee42\0").unwrap();
+    fs::write(
+        device_tree.join("model"),
+        b"Raspberry Pi 4 Model B Rev 1.1\0",
+    )
+    .unwrap();
+    let board = board_info_with_proc_root(&root).unwrap();
+    assert_eq!(board.revision, info_of("pi4_b").unwrap());
+    assert_eq!(board.serial.as_deref(), Some("10000000c0ffee42"));
+    assert_eq!(board.hardware, None);
+    assert_eq!(
+        board.model.as_deref(),
+        Some("Raspberry Pi 4 Model B Rev 1.1")
+    );
+}
+
+#[test]
+fn revision_codes_of_every_model() {
+    use rustpi_io::pi::Manufacturer::*;
+    use rustpi_io::pi::MemorySize::*;
+    use rustpi_io::pi::Processor::*;
+    use rustpi_io::pi::Type::*;
+    let boards = [
+        (0x0007, A, MB256, Egoman, BCM2835, 0x10),
+        (0x0009, A, MB256, Qisda, BCM2835, 0x10),
+        (0x0003, B, MB256, Egoman, BCM2835, 0x00),
+        (0x000f, B, MB512, Egoman, BCM2835, 0x10),
+        (0x0012, APlus, MB256, SonyUK, BCM2835, 0x01),
+        (0x0015, APlus, MB256, Embest, BCM2835, 0x01),
+        (0x0900021, APlus, MB512, SonyUK, BCM2835, 1),
+        (0x0010, BPlus, MB512, SonyUK, BCM2835, 0x02),
+        (0x0900032, BPlus, MB512, SonyUK, BCM2835, 2),
+        (0x0011, CM1, MB512, SonyUK, BCM2835, 0x00),
+        (0x0014, CM1, MB512, Embest, BCM2835, 0x00),
+        (0xa01041, B2, MB1024, SonyUK, BCM2836, 1),
+        (0xa21041, B2, MB1024, Embest, BCM2836, 1),
+        (0xa22042, B2, MB1024, Embest, BCM2837, 2),
+        (0x900093, Zero, MB512, SonyUK, BCM2835, 3),
+        (0x920093, Zero, MB512, Embest, BCM2835, 3),
+        (0x9000c1, ZeroW, MB512, SonyUK, BCM2835, 1),
+        (0x902120, Zero2W, MB512, SonyUK, BCM2837, 0),
+        (0xa32082, B3, MB1024, SonyJapan, BCM2837, 2),
+        (0xa52082, B3, MB1024, Stadium, BCM2837, 2),
+        (0xa020a0, CM3, MB1024, SonyUK, BCM2837, 0),
+        (0xa020d3, B3Plus, MB1024, SonyUK, BCM2837, 3),
+        (0x9020e0, A3Plus, MB512, SonyUK, BCM2837, 0),
+        (0xa02100, CM3Plus, MB1024, SonyUK, BCM2837, 0),
+        (0xa03111, B4, MB1024, SonyUK, BCM2711, 1),
+        (0xd03114, B4, MB8192, SonyUK, BCM2711, 4),
+        (0xc03130, Pi400, MB4096, SonyUK, BCM2711, 0),
+        (0xb03140, CM4, MB2048, SonyUK, BCM2711, 0),
+        (0xa03150, CM4S, MB1024, SonyUK, BCM2711, 0),
+        (0xc04170, B5, MB4096, SonyUK, BCM2712, 0),
+        (0xe04171, B5, MB16384, SonyUK, BCM2712, 1),
+        (0xd04180, CM5, MB8192, SonyUK, BCM2712, 0),
+        (0xd04190, Pi500, MB8192, SonyUK, BCM2712, 0),
+        (0xd041a0, CM5Lite, MB8192, SonyUK, BCM2712, 0),
+    ];
+    for (code, model, memory, manufacturer, processor, pcb_revision) in boards {
+        let info = decode_revision(code).unwrap();
+        assert_eq!(
+            (
+                info.model,
+                info.memory,
+                info.manufacturer,
+                info.processor,
+                info.pcb_revision
+            ),
+            (model, memory, manufacturer, processor, pcb_revision),
+            "revision {:x}",
+            code
+        );
+    }
+}
+
+#[test]
+fn manufacturer_codes() {
+    let manufacturers = [
+        Manufacturer::SonyUK,
+        Manufacturer::Egoman,
+        Manufacturer::Embest,
+        Manufacturer::SonyJapan,
+        Manufacturer::Embest,
+        Manufacturer::Stadium,
+    ];
+    for (code, manufacturer) in manufacturers.iter().enumerate() {
+        assert_eq!(&Manufacturer::from(code as u32), manufacturer);
+    }
+    // the specification lists no further manufacturers
+    for code in 6..16 {
+        assert_eq!(Manufacturer::from(code), Manufacturer::Unknown(code));
+    }
+    let info = decode_revision(0x22a2_2082).unwrap();
+    assert_eq!(
+        info.to_string(),
+        "B3 rev 1.2, 1024MB, BCM2837, Embest (22a22082)"
+    );
+    assert!(info.warranty_void && info.otp_read_disabled && !info.overvoltage_disallowed);
+}
+
+#[test]
+fn unknown_fields_of_new_style_codes() {
+    let info = decode_revision(0x00f6_ff70).unwrap();
+    assert_eq!(
+        info.to_string(),
+        "unknown(247) rev 1.0, unknown(7), unknown(15), unknown(6) (f6ff70)"
+    );
+}
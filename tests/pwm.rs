@@ -0,0 +1,86 @@
+extern crate rustpi_io;
+
+mod common;
+
+use common::TempDir;
+use rustpi_io::pwm::{Polarity, PWM};
+use rustpi_io::Error;
+use std::fs;
+use std::time::Duration;
+
+/// Creates a pwmchip0 with channel 1 like the kernel does after the export.
+fn fake_tree(name: &str) -> TempDir {
+    let root = TempDir::new("pwm", name);
+    let chip = root.join("pwmchip0");
+    fs::create_dir_all(chip.join("pwm1")).unwrap();
+    for file in ["export", "unexport"].iter() {
+        fs::write(chip.join(file), "").unwrap();
+    }
+    for (file, value) in [
+        ("period", "0"),
+        ("duty_cycle", "0"),
+        ("polarity", "normal"),
+        ("enable", "0"),
+    ]
+    .iter()
+    {
+        fs::write(chip.join("pwm1").join(file), value).unwrap();
+    }
+    root
+}
+
+#[test]
+fn configure_channel() {
+    let root = fake_tree("configure");
+    let chip = root.join("pwmchip0");
+    {
+        let pwm = PWM::with_sysfs_root(&root, 0, 1).unwrap();
+        assert_eq!(fs::read_to_string(chip.join("export")).unwrap(), "1");
+        pwm.set_period(Duration::from_millis(20)).unwrap();
+        pwm.set_duty_ratio(0.25).unwrap();
+        assert_eq!(pwm.duty_cycle().unwrap(), Duration::from_millis(5));
+        pwm.set_frequency(100.0).unwrap();
+        assert_eq!(pwm.period().unwrap(), Duration::from_millis(10));
+        assert!((pwm.duty_ratio().unwrap() - 0.25).abs() < 1e-9);
+        assert!((pwm.frequency().unwrap() - 100.0).abs() < 1e-9);
+        pwm.set_polarity(Polarity::Inversed).unwrap();
+        assert_eq!(pwm.polarity().unwrap(), Polarity::Inversed);
+        pwm.enable().unwrap();
+        assert!(pwm.is_enabled().unwrap());
+    }
+    assert_eq!(fs::read_to_string(chip.join("pwm1/enable")).unwrap(), "0");
+    assert_eq!(fs::read_to_string(chip.join("unexport")).unwrap(), "1");
+}
+
+#[test]
+fn reject_invalid_arguments() {
+    let root = fake_tree("invalid");
+    let pwm = PWM::with_sysfs_root(&root, 0, 1).unwrap();
+    pwm.set_period(Duration::from_millis(20)).unwrap();
+    for frequency in [0.0, -1.0, 1e-300, f64::NAN, f64::INFINITY].iter() {
+        match pwm.set_frequency(*frequency) {
+            Err(Error::InvalidArgument(_)) => {}
+            other => panic!("frequency {}: {:?}", frequency, other),
+        }
+    }
+    assert!(pwm.set_duty_ratio(1.5).is_err());
+    assert_eq!(pwm.period().unwrap(), Duration::from_millis(20));
+}
+
+#[test]
+fn missing_chip() {
+    let root = fake_tree("missing");
+    match PWM::with_sysfs_root(&root, 1, 0) {
+        Err(Error::DeviceNotFound { path, .. }) => assert_eq!(path, root.join("pwmchip1")),
+        Err(why) => panic!("{:?}", why),
+        Ok(_) => panic!("pwmchip1 does not exist"),
+    }
+}
+
+#[test]
+fn drop_after_the_tree_is_gone() {
+    let root = fake_tree("gone");
+    let pwm = PWM::with_sysfs_root(&root, 0, 1).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+    drop(pwm);
+}
@@ -0,0 +1,220 @@
+extern crate rustpi_io;
+
+use rustpi_io::serial::{ComMode, SerialPi, Speed, SpiMode, SpiTransport, Transfer};
+use rustpi_io::Error;
+use std::io::{self, BufRead, Read, Write};
+
+/// A transfer as it was sent to the mock bus.
+#[derive(Debug, PartialEq)]
+enum Sent {
+    Write(Vec<u8>),
+    Read(usize),
+    ReadWrite(Vec<u8>),
+}
+
+/// Answers every clocked byte with the next value of a counter and records the transfers.
+/// Like the controller of the raspberry pi it can't send the least significant bit first.
+#[derive(Default)]
+struct MockSpi {
+    transfers: Vec<Sent>,
+    next: u8,
+    speed_hz: u32,
+    mode: u32,
+    bits_per_word: u8,
+}
+
+impl MockSpi {
+    fn answer(&mut self, rx: &mut [u8]) {
+        for byte in rx.iter_mut() {
+            *byte = self.next;
+            self.next = self.next.wrapping_add(1);
+        }
+    }
+}
+
+impl SpiTransport for MockSpi {
+    fn transfer(&mut self, transfer: Transfer, _keep_selected: bool) -> rustpi_io::Result<()> {
+        match transfer {
+            Transfer::Write(tx) => {
+                self.transfers.push(Sent::Write(tx.to_vec()));
+                self.answer(&mut vec![0; tx.len()]);
+            }
+            Transfer::Read(rx) => {
+                self.transfers.push(Sent::Read(rx.len()));
+                self.answer(rx);
+            }
+            Transfer::ReadWrite(tx, rx) => {
+                self.transfers.push(Sent::ReadWrite(tx.to_vec()));
+                self.answer(rx);
+            }
+            Transfer::InPlace(buf) => {
+                self.transfers.push(Sent::ReadWrite(buf.to_vec()));
+                self.answer(buf);
+            }
+        }
+        Ok(())
+    }
+
+    fn max_transfer_size(&self) -> usize {
+        4096
+    }
+
+    fn set_speed_hz(&mut self, speed_hz: u32) -> rustpi_io::Result<()> {
+        self.speed_hz = speed_hz;
+        Ok(())
+    }
+
+    fn speed_hz(&self) -> rustpi_io::Result<u32> {
+        Ok(self.speed_hz)
+    }
+
+    fn set_mode(&mut self, mode: u32) -> rustpi_io::Result<()> {
+        self.mode = mode;
+        Ok(())
+    }
+
+    fn mode(&self) -> rustpi_io::Result<u32> {
+        Ok(self.mode)
+    }
+
+    fn set_lsb_first(&mut self, lsb_first: bool) -> rustpi_io::Result<()> {
+        if lsb_first {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL).into());
+        }
+        Ok(())
+    }
+
+    fn lsb_first(&self) -> rustpi_io::Result<bool> {
+        Ok(false)
+    }
+
+    fn set_bits_per_word(&mut self, bits_per_word: u8) -> rustpi_io::Result<()> {
+        self.bits_per_word = bits_per_word;
+        Ok(())
+    }
+
+    fn bits_per_word(&self) -> rustpi_io::Result<u8> {
+        Ok(self.bits_per_word)
+    }
+}
+
+fn serial(com_mode: ComMode, buffer_capacity: usize) -> SerialPi<MockSpi> {
+    SerialPi::with_transport(
+        MockSpi::default(),
+        Speed::Khz976,
+        SpiMode::Mode3,
+        com_mode,
+        buffer_capacity,
+    )
+    .unwrap()
+}
+
+#[test]
+fn with_transport_configures_the_bus() {
+    let mut serial = serial(ComMode::FullDuplex, 8);
+    assert_eq!(serial.speed_hz().unwrap(), 976_001);
+    assert_eq!(serial.spi_mode().unwrap(), SpiMode::Mode3);
+    assert_eq!(serial.bits_per_word().unwrap(), 8);
+    assert!(!serial.lsb_first().unwrap());
+    serial.set_cs_high(true).unwrap();
+    assert_eq!(serial.transport().mode, 0x07);
+    serial.set_spi_mode(SpiMode::Mode1).unwrap();
+    assert_eq!(serial.transport().mode, 0x05);
+}
+
+#[test]
+fn fill_consume_and_read_in_full_duplex() {
+    let mut serial = serial(ComMode::FullDuplex, 8);
+    serial.write_all(&[0xA0, 0xA1, 0xA2]).unwrap();
+    // the received bytes are buffered and the rest of the capacity is read
+    assert_eq!(serial.fill_buf().unwrap(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+    // a full buffer reads nothing
+    assert_eq!(serial.fill_buf().unwrap(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+    serial.consume(2);
+    let mut buf = [0; 3];
+    serial.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [2, 3, 4]);
+    // the buffered bytes come first, the rest is read from the bus
+    let mut buf = [0; 6];
+    serial.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [5, 6, 7, 8, 9, 10]);
+    assert_eq!(
+        serial.fill_buf().unwrap(),
+        &[11, 12, 13, 14, 15, 16, 17, 18]
+    );
+    assert_eq!(
+        serial.into_inner().transfers,
+        vec![
+            Sent::ReadWrite(vec![0xA0, 0xA1, 0xA2]),
+            Sent::Read(5),
+            Sent::Read(3),
+            Sent::Read(8)
+        ]
+    );
+}
+
+#[test]
+fn half_duplex_does_not_touch_the_buffer() {
+    let mut serial = serial(ComMode::HalfDuplex, 8);
+    serial.write_all(&[0xA0, 0xA1]).unwrap();
+    assert!(serial.fill_buf().unwrap().is_empty());
+    let mut buf = [0; 2];
+    serial.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [2, 3]);
+    assert_eq!(
+        serial.transport().transfers,
+        vec![Sent::Write(vec![0xA0, 0xA1]), Sent::Read(2)]
+    );
+
+    // bytes buffered in full duplex mode are kept but not refilled or consumed
+    serial.com_mode = ComMode::FullDuplex;
+    serial.write_all(&[0xB0, 0xB1]).unwrap();
+    serial.com_mode = ComMode::HalfDuplex;
+    assert_eq!(serial.fill_buf().unwrap(), &[4, 5]);
+    serial.consume(1);
+    assert_eq!(serial.fill_buf().unwrap(), &[4, 5]);
+    // read still empties the buffer first
+    let mut buf = [0; 3];
+    serial.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [4, 5, 6]);
+    assert_eq!(serial.transport().transfers.len(), 4);
+}
+
+#[test]
+fn lsb_first_is_reversed_in_software() {
+    let mut serial = serial(ComMode::FullDuplex, 4);
+    serial.set_lsb_first(true).unwrap();
+    assert!(serial.lsb_first().unwrap());
+    serial.write_all(&[0x01, 0x03]).unwrap();
+    assert_eq!(serial.fill_buf().unwrap(), &[0x00, 0x80, 0x40, 0xC0]);
+    assert_eq!(
+        serial.transport().transfers,
+        vec![Sent::ReadWrite(vec![0x80, 0xC0]), Sent::Read(2)]
+    );
+}
+
+#[test]
+fn lsb_first_refills_whole_words() {
+    let mut serial = serial(ComMode::FullDuplex, 5);
+    serial.set_lsb_first(true).unwrap();
+    serial.set_bits_per_word(16).unwrap();
+    let word = |bytes: [u8; 2]| u16::from_ne_bytes(bytes).reverse_bits().to_ne_bytes();
+    // only two of the five bytes fit a word
+    let mut expected = word([0, 1]).to_vec();
+    expected.extend_from_slice(&word([2, 3]));
+    assert_eq!(serial.fill_buf().unwrap(), expected.as_slice());
+    serial.consume(2);
+    expected.drain(..2);
+    expected.extend_from_slice(&word([4, 5]));
+    assert_eq!(serial.fill_buf().unwrap(), expected.as_slice());
+
+    // a partial word is rejected before anything is sent
+    match serial.write(&[0x01, 0x02, 0x03]).map_err(Error::from) {
+        Err(Error::InvalidArgument(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(
+        serial.transport().transfers,
+        vec![Sent::Read(4), Sent::Read(2)]
+    );
+}
@@ -0,0 +1,190 @@
+extern crate rustpi_io;
+
+use rustpi_io::pwm::PwmOutput;
+use rustpi_io::servo::{Servo, ServoCalibration};
+use rustpi_io::Error;
+use std::cell::RefCell;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Call {
+    Period(Duration),
+    DutyCycle(Duration),
+    Enable,
+}
+
+/// Records the calls and rejects a duty cycle longer than the period like the kernel does.
+struct MockPwm {
+    calls: RefCell<Vec<Call>>,
+    period: RefCell<Duration>,
+    duty_cycle: RefCell<Duration>,
+}
+
+impl MockPwm {
+    /// A channel that still has the settings of a previous user
+    fn new() -> MockPwm {
+        MockPwm {
+            calls: RefCell::new(Vec::new()),
+            period: RefCell::new(Duration::from_millis(50)),
+            duty_cycle: RefCell::new(Duration::from_millis(40)),
+        }
+    }
+}
+
+impl PwmOutput for MockPwm {
+    fn set_period(&self, period: Duration) -> rustpi_io::Result<()> {
+        self.calls.borrow_mut().push(Call::Period(period));
+        if period < *self.duty_cycle.borrow() {
+            return Err(Error::InvalidArgument(
+                "period is shorter than the duty cycle",
+            ));
+        }
+        *self.period.borrow_mut() = period;
+        Ok(())
+    }
+
+    fn set_duty_cycle(&self, duty_cycle: Duration) -> rustpi_io::Result<()> {
+        self.calls.borrow_mut().push(Call::DutyCycle(duty_cycle));
+        if duty_cycle > *self.period.borrow() {
+            return Err(Error::InvalidArgument(
+                "duty cycle is longer than the period",
+            ));
+        }
+        *self.duty_cycle.borrow_mut() = duty_cycle;
+        Ok(())
+    }
+
+    fn enable(&self) -> rustpi_io::Result<()> {
+        self.calls.borrow_mut().push(Call::Enable);
+        Ok(())
+    }
+}
+
+fn servo() -> Servo<MockPwm> {
+    Servo::new(MockPwm::new(), ServoCalibration::default()).unwrap()
+}
+
+fn last_call(servo: Servo<MockPwm>) -> Call {
+    *servo.into_inner().calls.borrow().last().unwrap()
+}
+
+fn assert_invalid_argument(result: rustpi_io::Result<()>) {
+    match result {
+        Err(Error::InvalidArgument(_)) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn new_clears_the_duty_cycle_before_the_period() {
+    let servo = servo();
+    assert_eq!(servo.pulse_width(), None);
+    assert_eq!(servo.angle(), None);
+    // the old duty cycle is longer than the new period of 20ms
+    assert_eq!(
+        *servo.into_inner().calls.borrow(),
+        vec![
+            Call::DutyCycle(Duration::from_secs(0)),
+            Call::Period(Duration::from_millis(20)),
+            Call::Enable
+        ]
+    );
+}
+
+#[test]
+fn angles_map_to_pulse_widths() {
+    for &(angle, micros) in [(0.0, 1000), (45.0, 1250), (90.0, 1500), (180.0, 2000)].iter() {
+        let mut servo = servo();
+        servo.set_angle(angle).unwrap();
+        let pulse_width = Duration::from_micros(micros);
+        assert_eq!(servo.pulse_width(), Some(pulse_width));
+        assert!((servo.angle().unwrap() - angle).abs() < 1e-9);
+        assert_eq!(last_call(servo), Call::DutyCycle(pulse_width));
+    }
+}
+
+#[test]
+fn calibrated_ranges() {
+    let calibration = ServoCalibration {
+        min_pulse: Duration::from_micros(500),
+        max_pulse: Duration::from_micros(2500),
+        min_angle: -90.0,
+        max_angle: 90.0,
+        period: Duration::from_millis(10),
+    };
+    let mut servo = Servo::new(MockPwm::new(), calibration).unwrap();
+    assert_eq!(servo.calibration(), &calibration);
+    servo.set_angle(-45.0).unwrap();
+    assert_eq!(servo.pulse_width(), Some(Duration::from_micros(1000)));
+    servo.set_pulse_width(Duration::from_micros(2000)).unwrap();
+    assert!((servo.angle().unwrap() - 45.0).abs() < 1e-9);
+}
+
+#[test]
+fn reject_values_outside_of_the_range() {
+    let mut servo = servo();
+    servo.set_angle(90.0).unwrap();
+    for &angle in [-0.1, 180.1, f64::NAN, f64::INFINITY].iter() {
+        assert_invalid_argument(servo.set_angle(angle));
+    }
+    for &micros in [999, 2001, 0].iter() {
+        assert_invalid_argument(servo.set_pulse_width(Duration::from_micros(micros)));
+    }
+    // the servo keeps its position
+    assert_eq!(servo.pulse_width(), Some(Duration::from_micros(1500)));
+    assert_eq!(
+        last_call(servo),
+        Call::DutyCycle(Duration::from_micros(1500))
+    );
+}
+
+#[test]
+fn invalid_calibrations() {
+    let valid = ServoCalibration::default();
+    let invalid = [
+        ServoCalibration {
+            min_pulse: valid.max_pulse,
+            ..valid
+        },
+        ServoCalibration {
+            max_pulse: Duration::from_millis(21),
+            ..valid
+        },
+        ServoCalibration {
+            min_angle: 180.0,
+            ..valid
+        },
+        ServoCalibration {
+            min_angle: f64::NEG_INFINITY,
+            ..valid
+        },
+        ServoCalibration {
+            max_angle: f64::NAN,
+            ..valid
+        },
+    ];
+    for calibration in invalid.iter() {
+        let pwm = MockPwm::new();
+        match Servo::new(pwm, *calibration) {
+            Err(Error::InvalidArgument(_)) => {}
+            Err(why) => panic!("{:?}: {:?}", calibration, why),
+            Ok(_) => panic!("{:?} was accepted", calibration),
+        }
+    }
+    // the period may be as long as the maximal pulse
+    let calibration = ServoCalibration {
+        period: valid.max_pulse,
+        ..valid
+    };
+    assert!(Servo::new(MockPwm::new(), calibration).is_ok());
+}
+
+#[test]
+fn relax_stops_the_pulses() {
+    let mut servo = servo();
+    servo.set_angle(30.0).unwrap();
+    servo.relax().unwrap();
+    assert_eq!(servo.pulse_width(), None);
+    assert_eq!(servo.angle(), None);
+    assert_eq!(last_call(servo), Call::DutyCycle(Duration::from_secs(0)));
+}
@@ -0,0 +1,313 @@
+extern crate rustpi_io;
+
+use rustpi_io::gpio::{GPIOData, OpenDrainPin};
+use rustpi_io::i2c::{self, Address, I2cPi, I2cTransport, Message};
+use rustpi_io::soft_i2c::SoftI2c;
+use rustpi_io::Error;
+use std::cell::RefCell;
+use std::io::ErrorKind;
+use std::rc::Rc;
+use std::time::Duration;
+
+const EEPROM_ADDRESS: u8 = 0x50;
+
+#[derive(PartialEq, Clone, Copy)]
+enum Phase {
+    Data,
+    Acknowledge,
+}
+
+/// Two open drain lines with a simulated eeprom like slave: The first written byte sets the
+/// register pointer, following bytes are written to the registers and reads start at the pointer.
+struct Bus {
+    master_scl_low: bool,
+    master_sda_low: bool,
+    slave_sda_low: bool,
+    /// The slave stretches the clock
+    slave_scl_low: bool,
+    /// Another master drives the data line
+    other_master_sda_low: bool,
+    addressed: bool,
+    address_byte: bool,
+    reading: bool,
+    shift: u8,
+    bits: u8,
+    phase: Phase,
+    master_acknowledged: bool,
+    registers: [u8; 256],
+    pointer: u8,
+    pointer_set: bool,
+    sending: u8,
+}
+
+impl Bus {
+    fn new() -> Bus {
+        let mut registers = [0; 256];
+        for (index, register) in registers.iter_mut().enumerate() {
+            *register = index as u8 ^ 0xA5;
+        }
+        Bus {
+            master_scl_low: false,
+            master_sda_low: false,
+            slave_sda_low: false,
+            slave_scl_low: false,
+            other_master_sda_low: false,
+            addressed: false,
+            address_byte: false,
+            reading: false,
+            shift: 0,
+            bits: 0,
+            phase: Phase::Data,
+            master_acknowledged: false,
+            registers,
+            pointer: 0,
+            pointer_set: false,
+            sending: 0,
+        }
+    }
+
+    fn scl(&self) -> bool {
+        !(self.master_scl_low || self.slave_scl_low)
+    }
+
+    fn sda(&self) -> bool {
+        !(self.master_sda_low || self.slave_sda_low || self.other_master_sda_low)
+    }
+
+    fn idle(&self) -> bool {
+        self.scl() && self.sda()
+    }
+
+    /// Reacts to the change of the lines from the given levels to the current ones
+    fn update(&mut self, old_scl: bool, old_sda: bool) {
+        let (scl, sda) = (self.scl(), self.sda());
+        if old_scl && scl && old_sda && !sda {
+            // (repeated) start
+            self.addressed = true;
+            self.address_byte = true;
+            self.bits = 0;
+            self.shift = 0;
+            self.phase = Phase::Data;
+            self.reading = false;
+            self.slave_sda_low = false;
+            self.pointer_set = false;
+            return;
+        }
+        if old_scl && scl && !old_sda && sda {
+            // stop
+            self.addressed = false;
+            self.slave_sda_low = false;
+            return;
+        }
+        if !self.addressed {
+            return;
+        }
+        if !old_scl && scl {
+            // rising edge: sample the data line
+            match self.phase {
+                Phase::Data => {
+                    self.bits += 1;
+                    if !self.reading {
+                        self.shift = self.shift << 1 | sda as u8;
+                    }
+                }
+                Phase::Acknowledge => {
+                    if self.reading {
+                        self.master_acknowledged = !sda;
+                    }
+                }
+            }
+        }
+        if old_scl && !scl {
+            // falling edge: change the data line
+            match self.phase {
+                Phase::Data if self.bits == 8 => {
+                    if self.reading {
+                        self.slave_sda_low = false;
+                    } else {
+                        self.received(self.shift);
+                        if !self.addressed {
+                            return;
+                        }
+                        self.slave_sda_low = true;
+                    }
+                    self.phase = Phase::Acknowledge;
+                }
+                Phase::Data => {
+                    if self.reading && self.bits > 0 {
+                        self.slave_sda_low = (self.sending >> (7 - self.bits)) & 1 == 0;
+                    }
+                }
+                Phase::Acknowledge => {
+                    self.phase = Phase::Data;
+                    self.bits = 0;
+                    self.shift = 0;
+                    self.slave_sda_low = false;
+                    if self.reading {
+                        if self.master_acknowledged {
+                            self.sending = self.registers[self.pointer as usize];
+                            self.pointer = self.pointer.wrapping_add(1);
+                            self.slave_sda_low = self.sending & 0x80 == 0;
+                        } else {
+                            self.addressed = false;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn received(&mut self, byte: u8) {
+        if self.address_byte {
+            self.address_byte = false;
+            if byte >> 1 != EEPROM_ADDRESS {
+                // not acknowledged
+                self.addressed = false;
+                return;
+            }
+            self.reading = byte & 1 == 1;
+            self.master_acknowledged = true;
+        } else if !self.pointer_set {
+            self.pointer = byte;
+            self.pointer_set = true;
+        } else {
+            self.registers[self.pointer as usize] = byte;
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Line {
+    Scl,
+    Sda,
+}
+
+struct SimulatedPin {
+    bus: Rc<RefCell<Bus>>,
+    line: Line,
+}
+
+impl SimulatedPin {
+    fn drive(&mut self, low: bool) -> rustpi_io::Result<()> {
+        let mut bus = self.bus.borrow_mut();
+        let (scl, sda) = (bus.scl(), bus.sda());
+        match self.line {
+            Line::Scl => bus.master_scl_low = low,
+            Line::Sda => bus.master_sda_low = low,
+        }
+        bus.update(scl, sda);
+        Ok(())
+    }
+}
+
+impl OpenDrainPin for SimulatedPin {
+    fn drive_low(&mut self) -> rustpi_io::Result<()> {
+        self.drive(true)
+    }
+
+    fn release(&mut self) -> rustpi_io::Result<()> {
+        self.drive(false)
+    }
+
+    fn level(&self) -> rustpi_io::Result<GPIOData> {
+        let bus = self.bus.borrow();
+        let high = match self.line {
+            Line::Scl => bus.scl(),
+            Line::Sda => bus.sda(),
+        };
+        Ok(if high { GPIOData::High } else { GPIOData::Low })
+    }
+}
+
+fn eeprom() -> (Rc<RefCell<Bus>>, I2cPi<SoftI2c<SimulatedPin>>) {
+    let bus = Rc::new(RefCell::new(Bus::new()));
+    let pin = |line| SimulatedPin {
+        bus: bus.clone(),
+        line,
+    };
+    let i2c = SoftI2c::new(pin(Line::Scl), pin(Line::Sda), 1_000_000).unwrap();
+    let pi = I2cPi::with_transport(i2c, Address::SevenBit(EEPROM_ADDRESS)).unwrap();
+    (bus, pi)
+}
+
+fn raw_os_error(error: Error) -> Option<i32> {
+    match error {
+        Error::Io(error) => error.raw_os_error(),
+        _ => None,
+    }
+}
+
+#[test]
+fn write_and_read_registers() {
+    let (bus, mut pi) = eeprom();
+    pi.smbus_write_byte_data(0x10, 0x42).unwrap();
+    assert_eq!(bus.borrow().registers[0x10], 0x42);
+    assert!(bus.borrow().idle());
+    assert_eq!(pi.smbus_read_byte_data(0x10).unwrap(), 0x42);
+    let mut rx = [0_u8; 3];
+    pi.write_read(&[0x20], &mut rx).unwrap();
+    assert_eq!(rx, [0x20 ^ 0xA5, 0x21 ^ 0xA5, 0x22 ^ 0xA5]);
+    assert_eq!(
+        pi.smbus_read_word_data(0x10).unwrap(),
+        u16::from_le_bytes([0x42, 0x11 ^ 0xA5])
+    );
+    pi.transfer(&mut [Message::Write(&[0x30, 1, 2, 3])])
+        .unwrap();
+    assert_eq!(&bus.borrow().registers[0x30..0x33], &[1, 2, 3]);
+}
+
+#[test]
+fn missing_acknowledge() {
+    let (bus, mut pi) = eeprom();
+    pi.set_slave_address(Address::SevenBit(EEPROM_ADDRESS + 1))
+        .unwrap();
+    let error = pi.smbus_read_byte().unwrap_err();
+    assert_eq!(raw_os_error(error), Some(libc::ENXIO));
+    // the bus is released with a stop condition
+    assert!(bus.borrow().idle());
+    assert!(!bus.borrow().addressed);
+}
+
+#[test]
+fn scan_finds_the_slave() {
+    let (bus, mut pi) = eeprom();
+    assert_eq!(i2c::scan(pi.transport_mut()).unwrap(), vec![EEPROM_ADDRESS]);
+    assert!(bus.borrow().idle());
+}
+
+#[test]
+fn clock_stretching_times_out() {
+    let (bus, mut pi) = eeprom();
+    pi.transport_mut()
+        .set_stretch_timeout(Duration::from_millis(5));
+    bus.borrow_mut().slave_scl_low = true;
+    let error = pi.smbus_read_byte().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::TimedOut);
+}
+
+#[test]
+fn arbitration_lost() {
+    let (bus, mut pi) = eeprom();
+    bus.borrow_mut().other_master_sda_low = true;
+    match pi.smbus_write_byte(1) {
+        Err(Error::Protocol(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    // no stop condition is sent, the other master owns the bus
+    let bus = bus.borrow();
+    assert!(!bus.master_scl_low && !bus.master_sda_low);
+}
+
+#[test]
+fn addresses_are_checked() {
+    let (bus, mut pi) = eeprom();
+    match pi
+        .transport_mut()
+        .transfer(Address::TenBit(0x400), &mut [Message::Write(&[])])
+    {
+        Err(Error::InvalidArgument(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    assert!(bus.borrow().idle());
+}
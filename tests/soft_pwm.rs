@@ -0,0 +1,80 @@
+extern crate rustpi_io;
+
+use rustpi_io::gpio::{DigitalPin, GPIOData};
+use rustpi_io::soft_pwm::SoftPwm;
+use rustpi_io::Error;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Records every level that is set.
+#[derive(Clone)]
+struct RecordingPin(Arc<Mutex<Vec<(Instant, GPIOData)>>>);
+
+impl RecordingPin {
+    fn new() -> RecordingPin {
+        RecordingPin(Arc::new(Mutex::new(Vec::new())))
+    }
+}
+
+impl DigitalPin for RecordingPin {
+    fn set(&self, data: GPIOData) -> rustpi_io::Result<()> {
+        self.0.lock().unwrap().push((Instant::now(), data));
+        Ok(())
+    }
+
+    fn value(&self) -> rustpi_io::Result<GPIOData> {
+        Ok(self
+            .0
+            .lock()
+            .unwrap()
+            .last()
+            .map_or(GPIOData::Low, |&(_, data)| data))
+    }
+}
+
+#[test]
+fn generate_signal() {
+    let pin = RecordingPin::new();
+    let pwm = SoftPwm::new(pin.clone(), 100.0, 0.25).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    pwm.set_duty_ratio(0.5).unwrap();
+    assert!((pwm.duty_ratio().unwrap() - 0.5).abs() < 1e-9);
+    assert!((pwm.frequency().unwrap() - 100.0).abs() < 1e-9);
+    thread::sleep(Duration::from_millis(50));
+    assert!(pwm.jitter().unwrap().samples > 0);
+    let pin = pwm.stop().unwrap();
+    let edges = pin.0.lock().unwrap();
+    assert!(edges.len() > 10);
+    assert_eq!(edges.last().unwrap().1, GPIOData::Low);
+}
+
+#[test]
+fn reject_invalid_arguments() {
+    for &frequency in [0.0, -1.0, 1e-300, f64::NAN].iter() {
+        match SoftPwm::new(RecordingPin::new(), frequency, 0.5) {
+            Err(Error::InvalidArgument(_)) => {}
+            Err(why) => panic!("frequency {}: {:?}", frequency, why),
+            Ok(_) => panic!("frequency {} was accepted", frequency),
+        }
+    }
+    assert!(SoftPwm::new(RecordingPin::new(), 10.0, 1.5).is_err());
+}
+
+#[test]
+fn failed_priority_returns_the_pin() {
+    let pin = RecordingPin::new();
+    // priorities above 99 are invalid for SCHED_FIFO, even for root
+    match SoftPwm::with_priority(pin.clone(), 10.0, 0.5, Some(200)) {
+        Err((returned, Error::Io(_))) => assert!(Arc::ptr_eq(&returned.0, &pin.0)),
+        Err((_, why)) => panic!("{:?}", why),
+        Ok(_) => panic!("priority 200 was accepted"),
+    }
+    assert!(pin.0.lock().unwrap().is_empty());
+    match SoftPwm::with_priority(pin.clone(), -1.0, 0.5, None) {
+        Err((returned, Error::InvalidArgument(_))) => {
+            assert!(Arc::ptr_eq(&returned.0, &pin.0))
+        }
+        _ => panic!("frequency -1 was accepted"),
+    }
+}
@@ -0,0 +1,262 @@
+extern crate rustpi_io;
+
+use rustpi_io::gpio::{DigitalPin, GPIOData};
+use rustpi_io::serial::{ComMode, SpiMode};
+use rustpi_io::soft_spi::SoftSpi;
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// An 8 bit shift register slave that answers every byte with the previous one.
+struct ShiftRegister {
+    mode: SpiMode,
+    lsb_first: bool,
+    register: u8,
+    clock: GPIOData,
+    mosi: GPIOData,
+    miso: GPIOData,
+    latched: GPIOData,
+    selected: bool,
+    /// Every MOSI bit in the order it was sampled
+    wire: Vec<u8>,
+}
+
+impl ShiftRegister {
+    fn new(mode: SpiMode, lsb_first: bool, register: u8) -> Self {
+        let idle = match mode {
+            SpiMode::Mode0 | SpiMode::Mode1 => GPIOData::Low,
+            SpiMode::Mode2 | SpiMode::Mode3 => GPIOData::High,
+        };
+        ShiftRegister {
+            mode,
+            lsb_first,
+            register,
+            clock: idle,
+            mosi: GPIOData::Low,
+            miso: GPIOData::Low,
+            latched: GPIOData::Low,
+            selected: false,
+            wire: Vec::new(),
+        }
+    }
+
+    fn idle(&self) -> GPIOData {
+        match self.mode {
+            SpiMode::Mode0 | SpiMode::Mode1 => GPIOData::Low,
+            SpiMode::Mode2 | SpiMode::Mode3 => GPIOData::High,
+        }
+    }
+
+    fn trailing_phase(&self) -> bool {
+        self.mode == SpiMode::Mode1 || self.mode == SpiMode::Mode3
+    }
+
+    fn present(&mut self) {
+        let bit = if self.lsb_first {
+            self.register & 0x01
+        } else {
+            self.register >> 7
+        };
+        self.miso = if bit == 1 {
+            GPIOData::High
+        } else {
+            GPIOData::Low
+        };
+    }
+
+    fn sample(&mut self) {
+        self.latched = self.mosi;
+    }
+
+    fn shift(&mut self) {
+        let bit = (self.latched == GPIOData::High) as u8;
+        self.wire.push(bit);
+        self.register = if self.lsb_first {
+            (self.register >> 1) | (bit << 7)
+        } else {
+            (self.register << 1) | bit
+        };
+    }
+
+    fn select(&mut self, level: GPIOData) {
+        self.selected = level == GPIOData::Low;
+        if self.selected && !self.trailing_phase() {
+            self.present();
+        }
+    }
+
+    fn clock(&mut self, level: GPIOData) {
+        if level == self.clock {
+            return;
+        }
+        self.clock = level;
+        if !self.selected {
+            return;
+        }
+        let leading = level != self.idle();
+        match (self.trailing_phase(), leading) {
+            // data is valid on the leading edge and changes on the trailing one
+            (false, true) => self.sample(),
+            (false, false) => {
+                self.shift();
+                self.present();
+            }
+            // data changes on the leading edge and is valid on the trailing one
+            (true, true) => self.present(),
+            (true, false) => {
+                self.sample();
+                self.shift();
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Line {
+    Sclk,
+    Mosi,
+    Miso,
+    ChipSelect,
+}
+
+#[derive(Clone)]
+struct FakePin {
+    slave: Rc<RefCell<ShiftRegister>>,
+    line: Line,
+}
+
+impl DigitalPin for FakePin {
+    fn set(&self, data: GPIOData) -> rustpi_io::Result<()> {
+        let mut slave = self.slave.borrow_mut();
+        match self.line {
+            Line::Sclk => slave.clock(data),
+            Line::Mosi => slave.mosi = data,
+            Line::ChipSelect => slave.select(data),
+            Line::Miso => panic!("MISO is driven by the slave"),
+        }
+        Ok(())
+    }
+
+    fn value(&self) -> rustpi_io::Result<GPIOData> {
+        let slave = self.slave.borrow();
+        Ok(match self.line {
+            Line::Miso => slave.miso,
+            Line::Sclk => slave.clock,
+            Line::Mosi => slave.mosi,
+            Line::ChipSelect => {
+                if slave.selected {
+                    GPIOData::Low
+                } else {
+                    GPIOData::High
+                }
+            }
+        })
+    }
+}
+
+fn soft_spi(
+    slave: &Rc<RefCell<ShiftRegister>>,
+    mode: SpiMode,
+    com_mode: ComMode,
+) -> SoftSpi<FakePin> {
+    let pin = |line| FakePin {
+        slave: slave.clone(),
+        line,
+    };
+    SoftSpi::new(
+        pin(Line::Sclk),
+        pin(Line::Mosi),
+        pin(Line::Miso),
+        Some(pin(Line::ChipSelect)),
+        mode,
+        com_mode,
+        Duration::from_secs(0),
+    )
+    .unwrap()
+}
+
+fn bits(byte: u8, lsb_first: bool) -> Vec<u8> {
+    (0..8)
+        .map(|index| {
+            let bit = if lsb_first { index } else { 7 - index };
+            (byte >> bit) & 1
+        })
+        .collect()
+}
+
+const MODES: [SpiMode; 4] = [
+    SpiMode::Mode0,
+    SpiMode::Mode1,
+    SpiMode::Mode2,
+    SpiMode::Mode3,
+];
+
+#[test]
+fn transfer_in_all_modes_and_bit_orders() {
+    for &mode in MODES.iter() {
+        for &lsb_first in [false, true].iter() {
+            let slave = Rc::new(RefCell::new(ShiftRegister::new(mode, lsb_first, 0xA5)));
+            let mut spi = soft_spi(&slave, mode, ComMode::FullDuplex);
+            spi.set_lsb_first(lsb_first);
+            let tx = [0x01, 0xC3, 0x5A];
+            let mut rx = [0u8; 3];
+            spi.transfer(&tx, &mut rx).unwrap();
+            assert_eq!(rx, [0xA5, 0x01, 0xC3], "{:?} lsb_first {}", mode, lsb_first);
+
+            let slave = slave.borrow();
+            assert_eq!(slave.register, 0x5A);
+            assert!(!slave.selected);
+            let wire: Vec<u8> = tx.iter().flat_map(|&byte| bits(byte, lsb_first)).collect();
+            assert_eq!(slave.wire, wire, "{:?} lsb_first {}", mode, lsb_first);
+        }
+    }
+}
+
+#[test]
+fn mismatched_bit_order_mirrors_bytes() {
+    let slave = Rc::new(RefCell::new(ShiftRegister::new(SpiMode::Mode0, true, 0)));
+    let mut spi = soft_spi(&slave, SpiMode::Mode0, ComMode::FullDuplex);
+    let mut rx = [0u8; 1];
+    spi.transfer(&[0x01], &mut rx).unwrap();
+    assert_eq!(slave.borrow().register, 0x80);
+}
+
+#[test]
+fn read_returns_buffered_bytes_in_full_duplex() {
+    let slave = Rc::new(RefCell::new(ShiftRegister::new(
+        SpiMode::Mode3,
+        false,
+        0x11,
+    )));
+    let mut spi = soft_spi(&slave, SpiMode::Mode3, ComMode::FullDuplex);
+    spi.write_all(&[0x22, 0x33]).unwrap();
+    let mut buf = [0u8; 3];
+    spi.read_exact(&mut buf).unwrap();
+    // the third byte is clocked out with a zero
+    assert_eq!(buf, [0x11, 0x22, 0x33]);
+    assert_eq!(slave.borrow().register, 0x00);
+}
+
+#[test]
+fn half_duplex_discards_received_bytes() {
+    let slave = Rc::new(RefCell::new(ShiftRegister::new(
+        SpiMode::Mode1,
+        false,
+        0x11,
+    )));
+    let mut spi = soft_spi(&slave, SpiMode::Mode1, ComMode::HalfDuplex);
+    spi.write_all(&[0x22]).unwrap();
+    let mut buf = [0u8; 1];
+    spi.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [0x22]);
+}
+
+#[test]
+fn transfer_rejects_different_lengths() {
+    let slave = Rc::new(RefCell::new(ShiftRegister::new(SpiMode::Mode0, false, 0)));
+    let mut spi = soft_spi(&slave, SpiMode::Mode0, ComMode::FullDuplex);
+    let mut rx = [0u8; 1];
+    assert!(spi.transfer(&[1, 2], &mut rx).is_err());
+    assert!(slave.borrow().wire.is_empty());
+}
@@ -0,0 +1,212 @@
+extern crate rustpi_io;
+
+mod common;
+
+use common::pty;
+use rustpi_io::gpio::{DigitalPin, GPIOData};
+use rustpi_io::uart::*;
+use rustpi_io::Error;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn with_timeout(read_timeout: Duration) -> UartConfig {
+    UartConfig {
+        baud_rate: 115_200,
+        read_timeout: Some(read_timeout),
+        ..Default::default()
+    }
+}
+
+/// Number of bytes that can be read from the descriptor
+fn available(fd: RawFd) -> i32 {
+    let mut count: libc::c_int = 0;
+    assert_eq!(unsafe { libc::ioctl(fd, libc::FIONREAD, &mut count) }, 0);
+    count
+}
+
+#[test]
+fn write_and_read_over_a_pty() {
+    let (mut master, path) = pty();
+    let mut uart = Uart::open(&path, with_timeout(Duration::from_millis(200))).unwrap();
+    uart.write_all(b"hi\n").unwrap();
+    uart.flush().unwrap();
+    let mut buf = [0_u8; 3];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hi\n");
+
+    // raw mode passes every byte unchanged
+    master.write_all(&[0x00, 0x03, 0x0D, 0x11, 0xFF]).unwrap();
+    let mut buf = [0_u8; 5];
+    uart.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [0x00, 0x03, 0x0D, 0x11, 0xFF]);
+}
+
+#[test]
+fn read_times_out() {
+    let (_master, path) = pty();
+    let mut uart = Uart::open(&path, with_timeout(Duration::from_millis(100))).unwrap();
+    let start = Instant::now();
+    let mut buf = [0_u8; 1];
+    assert_eq!(uart.read(&mut buf).unwrap_err().kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(90));
+}
+
+#[test]
+fn clear_input_discards_received_bytes() {
+    let (mut master, path) = pty();
+    let mut uart = Uart::open(&path, with_timeout(Duration::from_millis(100))).unwrap();
+    master.write_all(b"old").unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    uart.clear_input().unwrap();
+    master.write_all(b"new").unwrap();
+    let mut buf = [0_u8; 3];
+    uart.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"new");
+}
+
+#[test]
+fn reconfigure() {
+    let (_master, path) = pty();
+    let mut uart = Uart::open(&path, UartConfig::default()).unwrap();
+    assert!(uart.set_baud_rate(12_345).is_err());
+    assert_eq!(uart.config().baud_rate, 9600);
+    uart.set_baud_rate(57_600).unwrap();
+    assert_eq!(uart.config().baud_rate, 57_600);
+    let config = UartConfig {
+        parity: Parity::Even,
+        stop_bits: StopBits::Two,
+        data_bits: DataBits::Seven,
+        ..uart.config()
+    };
+    uart.set_config(config).unwrap();
+    assert_eq!(uart.config(), config);
+    match uart.set_read_timeout(Some(Duration::from_secs(30))) {
+        Err(Error::InvalidArgument(_)) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn open_missing_device() {
+    match Uart::open("/dev/does-not-exist", UartConfig::default()) {
+        Err(Error::DeviceNotFound { path, .. }) => {
+            assert_eq!(path, PathBuf::from("/dev/does-not-exist"))
+        }
+        Err(why) => panic!("{:?}", why),
+        Ok(_) => panic!("opened a missing device"),
+    }
+}
+
+/// A direction pin that records its levels together with the bytes that reached the other side.
+struct RecordingPin {
+    levels: Arc<Mutex<Vec<(GPIOData, i32, Instant)>>>,
+    master: RawFd,
+}
+
+impl DigitalPin for RecordingPin {
+    fn set(&self, data: GPIOData) -> rustpi_io::Result<()> {
+        self.levels
+            .lock()
+            .unwrap()
+            .push((data, available(self.master), Instant::now()));
+        Ok(())
+    }
+
+    fn value(&self) -> rustpi_io::Result<GPIOData> {
+        Ok(self
+            .levels
+            .lock()
+            .unwrap()
+            .last()
+            .map_or(GPIOData::Low, |&(data, _, _)| data))
+    }
+}
+
+#[test]
+fn rs485_without_a_pin_needs_the_kernel_mode() {
+    let (_master, path) = pty();
+    let mut uart = Uart::open(&path, UartConfig::default()).unwrap();
+    // a pty rejects TIOCSRS485
+    assert!(!uart.kernel_rs485_supported());
+    match uart.enable_rs485(Rs485Config::default(), None) {
+        Err((None, Error::Unsupported(_))) => {}
+        other => panic!("{:?}", other.map_err(|(_, why)| why)),
+    }
+    assert_eq!(uart.rs485_mode(), None);
+}
+
+/// A pin that can't be set
+struct BrokenPin;
+
+impl DigitalPin for BrokenPin {
+    fn set(&self, _data: GPIOData) -> rustpi_io::Result<()> {
+        Err(Error::Unsupported("broken pin"))
+    }
+
+    fn value(&self) -> rustpi_io::Result<GPIOData> {
+        Err(Error::Unsupported("broken pin"))
+    }
+}
+
+#[test]
+fn rs485_hands_back_a_pin_that_fails() {
+    let (_master, path) = pty();
+    let mut uart = Uart::open(&path, UartConfig::default()).unwrap();
+    match uart.enable_rs485(Rs485Config::default(), Some(Box::new(BrokenPin))) {
+        Err((Some(_), Error::Unsupported(_))) => {}
+        other => panic!("{:?}", other.map_err(|(_, why)| why)),
+    }
+    assert_eq!(uart.rs485_mode(), None);
+}
+
+#[test]
+fn rs485_switches_the_direction_pin() {
+    let (mut master, path) = pty();
+    let mut uart = Uart::open(&path, UartConfig::default()).unwrap();
+
+    let levels = Arc::new(Mutex::new(Vec::new()));
+    let pin = RecordingPin {
+        levels: levels.clone(),
+        master: master.as_raw_fd(),
+    };
+    let config = Rs485Config {
+        active_high: false,
+        delay_before_send: Duration::from_millis(0),
+        delay_after_send: Duration::from_millis(20),
+    };
+    assert_eq!(
+        uart.enable_rs485(config, Some(Box::new(pin)))
+            .map_err(|(_, why)| why)
+            .unwrap(),
+        Rs485Mode::Gpio
+    );
+    assert_eq!(uart.rs485_mode(), Some(Rs485Mode::Gpio));
+
+    uart.write_all(b"abc").unwrap();
+    {
+        let levels = levels.lock().unwrap();
+        let levels: Vec<(GPIOData, i32)> = levels
+            .iter()
+            .map(|&(data, count, _)| (data, count))
+            .collect();
+        // active low: receive, send, and receive again after the bytes were transmitted
+        assert_eq!(
+            levels,
+            vec![(GPIOData::High, 0), (GPIOData::Low, 0), (GPIOData::High, 3)]
+        );
+    }
+    let (_, _, sending) = levels.lock().unwrap()[1];
+    let (_, _, receiving) = levels.lock().unwrap()[2];
+    assert!(receiving - sending >= config.delay_after_send);
+
+    let mut buf = [0_u8; 3];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"abc");
+
+    assert!(uart.disable_rs485().unwrap().is_some());
+    assert_eq!(uart.rs485_mode(), None);
+    assert_eq!(levels.lock().unwrap().len(), 3);
+}